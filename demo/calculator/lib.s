@@ -1,20 +1,22 @@
-do_add:
+.global do_add, do_subtract, do_multiply, do_divide
+.extern get_two_numbers, print_result, print_string, main_loop, div_error_msg
+
+; Read two numbers, combine them with op, print the result and return to the menu
+.macro binary_op op
     call get_two_numbers
-    add r1 r2 r3
+    \op r1 r2 r3
     call print_result
     jmp main_loop
+.endm
+
+do_add:
+    binary_op add
 
 do_subtract:
-    call get_two_numbers
-    sub r1 r2 r3
-    call print_result
-    jmp main_loop
+    binary_op sub
 
 do_multiply:
-    call get_two_numbers
-    mul r1 r2 r3
-    call print_result
-    jmp main_loop
+    binary_op mul
 
 do_divide:
     call get_two_numbers
@@ -1,4 +1,6 @@
 .start _start
+.global main_loop, get_two_numbers, print_result, print_string, div_error_msg
+.extern do_add, do_subtract, do_multiply, do_divide
 
 .data
     welcome_msg: .string "Simple Calculator\n"
@@ -10,14 +12,18 @@
     invalid_choice_msg: .string "Invalid choice!\n"
     newline: .string "\n"
 
+; puts msg: print the null-terminated string at msg
+.macro puts msg
+    mov r1 \msg
+    call print_string
+.endm
+
 .text
 _start:
-    mov r1 welcome_msg
-    call print_string
+    puts welcome_msg
 
 main_loop:
-    mov r1 menu_msg
-    call print_string
+    puts menu_msg
 
     mov r0 #0
     input r1 r0
@@ -37,37 +43,32 @@ main_loop:
     cmp r1 #5
     jeq exit_program
 
-    mov r1 invalid_choice_msg
-    call print_string
+    puts invalid_choice_msg
     jmp main_loop
 
 get_two_numbers:
-    mov r1 num1_prompt
-    call print_string
+    puts num1_prompt
     mov r0 #0
     input r2 r0
 
-    mov r1 num2_prompt
-    call print_string
+    puts num2_prompt
     mov r0 #0
     input r3 r0
     ret
 
 print_result:
     push r1
-    mov r1 result_msg
-    call print_string
+    puts result_msg
     pop r1
     print r1
-    mov r1 newline
-    call print_string
+    puts newline
     ret
 
 print_string:
     push r1
     push r3
 print_loop:
-    load r3 [r1]
+    loadb r3 [r1]
     cmp r3 #0
     jeq end_print
     printc r3
@@ -0,0 +1,9 @@
+; RET checks the popped address against the one CALL recorded.
+    CALL clobber
+    HALT
+
+clobber:
+    POP r1
+    MOV r2, #99
+    PUSH r2
+    RET
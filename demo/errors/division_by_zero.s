@@ -0,0 +1,6 @@
+; Output printed before a runtime error is kept.
+    MOV r1, #10
+    PRINT r1
+    MOV r2, #0
+    DIV r3, r1, r2
+    HALT
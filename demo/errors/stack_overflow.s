@@ -0,0 +1,3 @@
+; Unbounded recursion overflows the stack instead of overwriting data.
+recurse:
+    CALL recurse
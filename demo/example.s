@@ -14,7 +14,7 @@ _start:
     MOV r1, prompt_msg          ; prompt user
     CALL print_string
 
-    INPUT r2                    ; get user input
+    INPUT r2, #0                ; get user input (mode 0: integer)
 
     MOV r1, result_msg          ; result message
     CALL print_string
@@ -30,7 +30,7 @@ print_string:
     PUSH r1                     ; save r1
     PUSH r3                     ; save r3 (used for character)
 loop:
-    LOAD r3, [r1]               ; load character from memory
+    LOADB r3, [r1]              ; load character from memory
     CMP r3, #0                  ; check for null terminator
     JEQ end_print               ; if null, exit
     PRINTC r3                   ; print character
@@ -1,4 +1,4 @@
-.start main
+.start _start
 
 .text
 _start:
@@ -0,0 +1,36 @@
+.start main
+
+; Data labels used as memory operands: absolute, base + offset and LEA.
+
+.data
+    total:  .word 0
+    values: .word 3, 5, 7, 11
+    count:  .word 4
+
+.text
+main:
+    LOAD r1, [count]            ; number of values
+    LEA r2, [values]            ; pointer to the first value
+    MOV r3, #0                  ; running sum
+
+sum_loop:
+    CMP r1, #0
+    JEQ done
+    LOAD r4, [r2]
+    ADD r3, r3, r4
+    ADD r2, r2, #4
+    SUB r1, r1, #1
+    JMP sum_loop
+
+done:
+    STORE r3, [total]
+    LOAD r5, [total]
+    PRINT r5                    ; 26
+    MOV r6, #10
+    PRINTC r6
+
+    LEA r2, [values]
+    LOAD r5, [r2 + 12]          ; last value
+    PRINT r5                    ; 11
+    PRINTC r6
+    HALT
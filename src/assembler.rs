@@ -1,13 +1,44 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use crate::assembler_error::{AssemblerError, AssemblerErrorKind};
+use crate::expression::{parse_expression, parse_string_literal, EvalError, Expr, Range, Symbols, CONSTANT_DEPTH_LIMIT};
+use crate::linker::{Field, Linker, ObjectFile, Relocation, Symbol};
 use crate::op_codes::OpCode;
-use crate::runtime::Instruction;
+use crate::preprocessor::{is_identifier, Expansion, Preprocessor};
+use crate::runtime::{Instruction, OperandMode, FRAME_POINTER, REGISTER_COUNT, STACK_POINTER};
 
 pub struct Assembler {
-    labels: HashMap<String, usize>,
+    labels: HashMap<String, (Symbol, SourceLocation)>,
     instructions: Vec<Instruction>,
     data_section: Vec<u8>,
-    start_label: Option<String>,
+    /// The largest alignment the data section needs, so the linker can place
+    /// it at a suitable address.
+    data_alignment: usize,
+    start_label: Option<(String, SourceLocation)>,
     current_section: Section,
+    relocations: Vec<Relocation>,
+    globals: Vec<(String, SourceLocation)>,
+    externs: HashMap<String, SourceLocation>,
+    defines: Vec<(String, i64)>,
+    errors: Vec<AssemblerError>,
+}
+
+/// A named piece of assembly source. `path` is only used in diagnostics.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub text: String,
+}
+
+impl SourceFile {
+    pub fn new(path: impl Into<String>, text: impl Into<String>) -> Self {
+        SourceFile {
+            path: path.into(),
+            text: text.into(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -16,374 +47,962 @@ enum Section {
     Data,
 }
 
+/// A piece of a source line, with its byte offset in the untrimmed line.
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    offset: usize,
+}
+
+/// Where a token came from, kept so errors found after the first pass can
+/// still point back at the source.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceLocation {
+    pub(crate) path: Rc<str>,
+    pub(crate) line: usize,
+    text: Rc<str>,
+    offset: usize,
+    length: usize,
+    expansion: Rc<[Expansion]>,
+}
+
+impl SourceLocation {
+    pub(crate) fn error(&self, kind: AssemblerErrorKind) -> AssemblerError {
+        let text = &self.text;
+        let offset = self.offset.min(text.len());
+        let end = (offset + self.length).min(text.len());
+        let column = text[..offset].chars().count() + 1;
+        let length = text[offset..end].chars().count();
+        let mut error = AssemblerError::new(kind, self.path.to_string(), self.line, column, length, text.to_string());
+        let mut calls: Vec<(String, usize)> = Vec::new();
+        for call in self.expansion.iter().rev() {
+            let note = call.to_string();
+            match calls.last_mut() {
+                Some((last, count)) if *last == note => *count += 1,
+                _ => calls.push((note, 1)),
+            }
+        }
+        error.notes = calls
+            .into_iter()
+            .map(|(note, count)| match count {
+                1 => note,
+                _ => format!("{} ({} times)", note, count),
+            })
+            .collect();
+        error
+    }
+}
+
+/// A source line ready to be assembled. Lines produced by a macro expansion
+/// keep the location of the macro body line they came from, plus the chain of
+/// invocations that produced them.
+#[derive(Debug, Clone)]
+pub(crate) struct Line {
+    pub(crate) path: Rc<str>,
+    pub(crate) number: usize,
+    /// The line as written, shown in diagnostics.
+    pub(crate) text: Rc<str>,
+    /// `text` with comments replaced by spaces; this is what gets parsed.
+    pub(crate) code: Rc<str>,
+    pub(crate) expansion: Rc<[Expansion]>,
+}
+
+impl Line {
+    pub(crate) fn location(&self, offset: usize, length: usize) -> SourceLocation {
+        SourceLocation {
+            path: self.path.clone(),
+            line: self.number,
+            text: self.text.clone(),
+            offset,
+            length,
+            expansion: self.expansion.clone(),
+        }
+    }
+
+    pub(crate) fn error(&self, kind: AssemblerErrorKind, offset: usize, length: usize) -> AssemblerError {
+        self.location(offset, length).error(kind)
+    }
+
+    fn token_error(&self, kind: AssemblerErrorKind, token: &Token) -> AssemblerError {
+        self.error(kind, token.offset, token.text.len())
+    }
+}
+
+/// What an instruction accepts in one operand position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OperandKind {
+    /// `rN`.
+    Register,
+    /// `rN`, `#expr` or a bare number. A bare name needs `#`, so a mistyped
+    /// register such as `rx` is not taken for a label.
+    RegisterOrImmediate,
+    /// `rN` or any expression, including a bare label (the source of MOV).
+    RegisterOrValue,
+    /// A label or expression naming an instruction.
+    Target,
+    /// `[rN]`, `[rN + expr]`, `[rN - expr]` or `[expr]`.
+    Memory,
+    /// `#expr` or a bare number.
+    Immediate,
+}
+
+impl OperandKind {
+    fn describe(self) -> &'static str {
+        match self {
+            OperandKind::Register => "a register",
+            OperandKind::RegisterOrImmediate => "a register or an immediate (#value)",
+            OperandKind::RegisterOrValue => "a register, an immediate or a label",
+            OperandKind::Target => "a label or instruction address",
+            OperandKind::Memory => "a memory operand such as [r1 + 4]",
+            OperandKind::Immediate => "an immediate (#value)",
+        }
+    }
+
+    /// Checks the form of an operand; its value is parsed later.
+    fn check(self, line: &Line, token: &Token) -> Result<(), AssemblerError> {
+        let text = token.text.as_str();
+        let is_register = Assembler::looks_like_register(text);
+        if self == OperandKind::Register
+            || (is_register && matches!(self, OperandKind::RegisterOrImmediate | OperandKind::RegisterOrValue))
+        {
+            return Assembler::register(line, token).map(|_| ());
+        }
+
+        let bare_number = text.starts_with(|c: char| c.is_ascii_digit() || "-+~!('".contains(c));
+        let accepted = match self {
+            OperandKind::Register => unreachable!("registers are checked above"),
+            OperandKind::RegisterOrImmediate | OperandKind::Immediate => text.starts_with('#') || bare_number,
+            OperandKind::RegisterOrValue | OperandKind::Target => !is_register,
+            OperandKind::Memory => text.starts_with('[') && text.ends_with(']'),
+        };
+        if accepted {
+            return Ok(());
+        }
+        Err(line.token_error(
+            AssemblerErrorKind::UnexpectedOperand {
+                expected: self.describe().to_string(),
+                found: text.to_string(),
+            },
+            token,
+        ))
+    }
+}
+
+/// The operands an instruction is written with. Operands past the first
+/// `required` are optional.
+struct Signature {
+    operands: &'static [OperandKind],
+    required: usize,
+}
+
+impl Signature {
+    fn of(opcode: OpCode) -> Signature {
+        use OperandKind::*;
+        let (operands, required): (&'static [OperandKind], usize) = match opcode {
+            OpCode::RET | OpCode::HALT | OpCode::NOP => (&[], 0),
+            OpCode::PUSH | OpCode::POP | OpCode::PRINT | OpCode::PRINTC => (&[Register], 1),
+            OpCode::MOV => (&[Register, RegisterOrValue], 2),
+            OpCode::CMP => (&[Register, RegisterOrImmediate], 2),
+            OpCode::NOT => (&[Register, Register], 2),
+            OpCode::LEA | OpCode::LOAD | OpCode::LOADB | OpCode::STORE => (&[Register, Memory], 2),
+            OpCode::ADD | OpCode::SUB | OpCode::MUL | OpCode::DIV | OpCode::AND | OpCode::OR | OpCode::XOR => {
+                (&[Register, Register, RegisterOrImmediate], 3)
+            }
+            OpCode::LSL | OpCode::LSR => (&[Register, Register, Immediate], 3),
+            // The buffer register is only used by the string mode, and is
+            // required when that mode is given as an immediate.
+            OpCode::INPUT => (&[Register, RegisterOrImmediate, Register], 2),
+            _ => (&[Target], 1),
+        };
+        Signature { operands, required }
+    }
+}
+
+/// Symbol values available while a file is being assembled: constants
+/// defined so far. Labels only get addresses when the program is linked.
+struct LocalSymbols<'a>(&'a HashMap<String, (Symbol, SourceLocation)>);
+
+impl Symbols for LocalSymbols<'_> {
+    fn value(&self, name: &str, depth: usize) -> Result<i64, EvalError> {
+        match self.0.get(name) {
+            Some((Symbol::Constant(expr), _)) => {
+                if depth >= CONSTANT_DEPTH_LIMIT {
+                    return Err(AssemblerErrorKind::CircularConstant(name.to_string()).into());
+                }
+                expr.evaluate(self, depth + 1)
+            }
+            _ => Err(EvalError::Unresolved),
+        }
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Assembler {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Assembler {
             labels: HashMap::new(),
             instructions: Vec::new(),
             data_section: Vec::new(),
+            data_alignment: 1,
             start_label: None,
             current_section: Section::Text,
+            relocations: Vec::new(),
+            globals: Vec::new(),
+            externs: HashMap::new(),
+            defines: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
+    /// Predefines a constant in every file, as if each started with
+    /// `.equ name, value`. Used by `.if`/`.ifdef` and in expressions.
+    pub fn define(&mut self, name: impl Into<String>, value: i64) -> &mut Self {
+        self.defines.push((name.into(), value));
+        self
+    }
+
     #[inline]
     fn parse_register(reg: &str) -> Option<u8> {
-        if reg.starts_with('r') || reg.starts_with('R') {
-            reg[1..].parse().ok()
-        } else {
-            None
+        if reg.eq_ignore_ascii_case("sp") {
+            return Some(STACK_POINTER as u8);
+        }
+        if reg.eq_ignore_ascii_case("fp") {
+            return Some(FRAME_POINTER as u8);
         }
+        if !Self::looks_like_register(reg) {
+            return None;
+        }
+        reg[1..].parse().ok().filter(|&index: &u8| (index as usize) < REGISTER_COUNT)
     }
 
+    /// Whether `text` is written like a register (`r` and a number, `sp` or
+    /// `fp`), even if there is no such register.
     #[inline]
-    fn parse_immediate(imm: &str) -> Option<i32> {
-        if imm.starts_with('#') {
-            imm[1..].parse().ok()
-        } else if imm.starts_with("0x") {
-            i32::from_str_radix(&imm[2..], 16).ok()
-        } else {
-            imm.parse().ok()
-        }
+    fn looks_like_register(text: &str) -> bool {
+        text.eq_ignore_ascii_case("sp")
+            || text.eq_ignore_ascii_case("fp")
+            || (text.len() > 1 && text.starts_with(['r', 'R']) && text[1..].bytes().all(|b| b.is_ascii_digit()))
     }
 
-    fn parse_instruction_parts(line: &str) -> Vec<String> {
-        let mut parts = Vec::new();
-        let mut current = String::new();
-        let mut in_brackets = false;
+    /// Parses an operand that must be a register.
+    fn register(line: &Line, token: &Token) -> Result<u8, AssemblerError> {
+        Self::parse_register(&token.text).ok_or_else(|| {
+            let kind = if Self::looks_like_register(&token.text) {
+                AssemblerErrorKind::RegisterOutOfRange(token.text.clone())
+            } else {
+                AssemblerErrorKind::InvalidRegister(token.text.clone())
+            };
+            line.token_error(kind, token)
+        })
+    }
 
-        for ch in line.chars() {
-            match ch {
-                '[' => {
-                    in_brackets = true;
-                    current.push(ch);
-                }
-                ']' => {
-                    in_brackets = false;
-                    current.push(ch);
-                }
-                ',' if !in_brackets => {
-                    if !current.trim().is_empty() {
-                        parts.push(current.trim().to_string());
-                        current.clear();
-                    }
-                }
-                ch if ch.is_whitespace() && !in_brackets => {
-                    if !current.trim().is_empty() {
-                        parts.push(current.trim().to_string());
-                        current.clear();
-                    }
-                }
-                _ => {
-                    current.push(ch);
-                }
+    /// Parses an operand expression and evaluates it if it only uses constants
+    /// defined so far. Otherwise a relocation for `field` is recorded, and the
+    /// returned placeholder is overwritten by the linker.
+    fn expression_value(
+        &mut self,
+        line: &Line,
+        token: &Token,
+        text: &str,
+        field: Field,
+        range: Range,
+    ) -> Result<i64, AssemblerError> {
+        let expr = parse_expression(text).map_err(|kind| line.token_error(kind, token))?;
+        match expr.evaluate(&LocalSymbols(&self.labels), 0) {
+            Ok(value) => range.check(value).map_err(|kind| line.token_error(kind, token)),
+            Err(EvalError::Invalid(kind)) => Err(line.token_error(kind, token)),
+            Err(EvalError::Unresolved) => {
+                self.relocations.push(Relocation {
+                    field,
+                    expr,
+                    range,
+                    location: line.location(token.offset, token.text.len()),
+                });
+                Ok(0)
             }
         }
+    }
 
-        if !current.trim().is_empty() {
-            parts.push(current.trim().to_string());
-        }
-
-        parts
+    /// The value of an immediate operand (`#expr` or a bare expression) of the
+    /// instruction about to be added.
+    fn immediate(&mut self, line: &Line, token: &Token, range: Range) -> Result<i32, AssemblerError> {
+        let text = token.text.strip_prefix('#').unwrap_or(&token.text);
+        let field = Field::Immediate(self.instructions.len());
+        Ok(self.expression_value(line, token, text, field, range)? as i32)
     }
 
-    pub(crate) fn assemble(&mut self, source: &str) -> Result<Vec<u8>, String> {
-        let lines: Vec<&str> = source.lines().collect();
+    /// Parses a memory operand of the form `[rs1]`, `[rs1 + expr]`, `[rs1 - expr]`
+    /// or `[expr]` into the instruction's base register, offset and mode. The
+    /// expression may carry an immediate's `#`, as the disassembler writes it.
+    fn memory_operand(&mut self, line: &Line, token: &Token, instruction: &mut Instruction) -> Result<(), AssemblerError> {
+        let addr_part = token.text.trim_start_matches('[').trim_end_matches(']').trim();
+        let base_len = addr_part.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(addr_part.len());
+        let field = Field::Immediate(self.instructions.len());
+
+        if Self::looks_like_register(&addr_part[..base_len]) {
+            let base = Token {
+                text: addr_part[..base_len].to_string(),
+                offset: token.offset + token.text.find(&addr_part[..base_len]).unwrap_or(0),
+            };
+            let reg = Self::register(line, &base)?;
+            instruction.rs1 = reg;
+            instruction.mode = OperandMode::Register;
+            let offset = addr_part[base_len..].trim();
+            if offset.is_empty() {
+                return Ok(());
+            }
+            if !offset.starts_with(['+', '-']) {
+                return Err(line.token_error(AssemblerErrorKind::InvalidMemoryOperand(token.text.clone()), token));
+            }
+            // The offset may be written as an immediate, as in `[r1 + #4]`.
+            let (sign, value) = offset.split_at(1);
+            let value = value.trim_start();
+            let offset = format!("{}{}", sign, value.strip_prefix('#').unwrap_or(value));
+            instruction.immediate = self.expression_value(line, token, &offset, field, Range::IMMEDIATE)? as i32;
+        } else {
+            let address = addr_part.strip_prefix('#').unwrap_or(addr_part);
+            instruction.mode = OperandMode::Immediate;
+            instruction.immediate = self.expression_value(line, token, address, field, Range::IMMEDIATE)? as i32;
+        }
+        Ok(())
+    }
 
-        for (line_num, line) in lines.iter().enumerate() {
-            let mut line = line.trim();
-            if line.is_empty() || line.starts_with(';') {
-                continue;
+    /// Splits a statement into its mnemonic and operands. Directive operands
+    /// and those of instructions that use any comma are separated by commas, so
+    /// expressions may contain spaces; other operands by whitespace. Brackets,
+    /// parentheses and character and string literals are kept whole.
+    fn parse_instruction_parts(line: &str, base: usize) -> Vec<Token> {
+        let mut separators = Vec::new();
+        let mut depth = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, ch) in line.char_indices() {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' if quote.is_some() => escaped = true,
+                '\'' | '"' if quote == Some(ch) => quote = None,
+                '\'' | '"' if quote.is_none() => quote = Some(ch),
+                _ if quote.is_some() => {}
+                '[' | '(' => depth += 1,
+                ']' | ')' => depth -= 1,
+                ',' | ' ' | '\t' if depth == 0 => separators.push((i, ch)),
+                _ => {}
             }
+        }
 
-            if line == ".data" {
-                self.current_section = Section::Data;
-                continue;
+        let mnemonic_end = line.find(char::is_whitespace).unwrap_or(line.len());
+        let comma_separated =
+            line.starts_with('.') || separators.iter().any(|&(i, ch)| ch == ',' && i > mnemonic_end);
+
+        let mut parts = Vec::new();
+        let mut push = |start: usize, stop: usize| {
+            let text = &line[start..stop];
+            if !text.trim().is_empty() {
+                let leading = text.len() - text.trim_start().len();
+                parts.push(Token {
+                    text: text.trim().to_string(),
+                    offset: base + start + leading,
+                });
             }
+        };
 
-            if line == ".text" {
-                self.current_section = Section::Text;
+        push(0, mnemonic_end);
+        let mut start = mnemonic_end;
+        for &(i, ch) in &separators {
+            if i < mnemonic_end || (comma_separated && ch != ',') {
                 continue;
             }
+            push(start, i);
+            start = i + 1;
+        }
+        push(start, line.len());
 
-            if line.starts_with(".start") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    self.start_label = Some(parts[1].to_string());
+        parts
+    }
+
+    /// Assembles each file into its own relocatable object and links them into
+    /// one program. Every error found is collected and returned together rather
+    /// than stopping at the first one.
+    pub fn assemble(&mut self, files: &[SourceFile]) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        let mut objects = Vec::with_capacity(files.len());
+
+        for file in files {
+            self.current_section = Section::Text;
+            self.predefine_constants();
+            let lines = Preprocessor::new(&self.defines).process(file, &mut self.errors);
+            for line in &lines {
+                // A line that fails may already have recorded relocations for
+                // an instruction or data item it never added.
+                let relocations = self.relocations.len();
+                if let Err(error) = self.assemble_line(line) {
+                    self.relocations.truncate(relocations);
+                    self.errors.push(error);
                 }
-                continue;
             }
+            objects.push(self.take_object());
+        }
 
-            if let Some(colon_pos) = line.find(':') {
-                let (label_part, code_part) = line.split_at(colon_pos);
-                let label = label_part.trim().to_string();
+        // Symbols are resolved even after errors, so undefined labels are
+        // reported together with everything else.
+        let linked = Linker::new().link(objects);
+        if self.errors.is_empty() {
+            return linked.map(|image| image.to_bytes());
+        }
+        let mut errors = std::mem::take(&mut self.errors);
+        if let Err(link_errors) = linked {
+            errors.extend(link_errors);
+        }
+        Err(errors)
+    }
 
-                if !label.contains(char::is_whitespace) {
-                    match self.current_section {
-                        Section::Text => {
-                            self.labels.insert(label, self.instructions.len());
-                        }
-                        Section::Data => {
-                            self.labels.insert(label, 512 + self.data_section.len());
-                        }
-                    }
+    /// Assembles a single in-memory source. `path` is the name used in diagnostics.
+    pub fn assemble_str(&mut self, path: &str, source: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        self.assemble(&[SourceFile::new(path, source)])
+    }
 
-                    line = code_part[1..].trim();
-                    if line.is_empty() {
-                        continue;
-                    }
-                }
+    /// Adds the `define`d constants to the symbols of the file about to be
+    /// assembled. They are reported as coming from the command line.
+    fn predefine_constants(&mut self) {
+        let path: Rc<str> = Rc::from("<command line>");
+        for (index, (name, value)) in self.defines.iter().enumerate() {
+            let text: Rc<str> = Rc::from(format!("-D {}={}", name, value));
+            let line = Line {
+                path: path.clone(),
+                number: index + 1,
+                text: text.clone(),
+                code: text,
+                expansion: Rc::from(Vec::new()),
+            };
+            let symbol = Symbol::Constant(Rc::new(Expr::Number(*value)));
+            self.labels.insert(name.clone(), (symbol, line.location(3, name.len())));
+        }
+    }
+
+    /// Moves the per-file state collected so far into an object file, leaving the
+    /// assembler ready for the next file.
+    fn take_object(&mut self) -> ObjectFile {
+        ObjectFile {
+            instructions: std::mem::take(&mut self.instructions),
+            data: std::mem::take(&mut self.data_section),
+            data_alignment: std::mem::replace(&mut self.data_alignment, 1),
+            symbols: std::mem::take(&mut self.labels)
+                .into_iter()
+                .map(|(name, (symbol, _))| (name, symbol))
+                .collect(),
+            globals: std::mem::take(&mut self.globals),
+            externs: std::mem::take(&mut self.externs),
+            relocations: std::mem::take(&mut self.relocations),
+            start: self.start_label.take(),
+        }
+    }
+
+    fn define_label(&mut self, line: &Line, name: String, offset: usize) -> Result<(), AssemblerError> {
+        let symbol = match self.current_section {
+            Section::Text => Symbol::Text(self.instructions.len()),
+            Section::Data => Symbol::Data(self.data_section.len()),
+        };
+        self.define_symbol(line, name, offset, symbol)
+    }
+
+    fn define_symbol(&mut self, line: &Line, name: String, offset: usize, symbol: Symbol) -> Result<(), AssemblerError> {
+        let location = line.location(offset, name.len());
+        if !is_identifier(&name) {
+            return Err(line.error(AssemblerErrorKind::InvalidSymbolName(name), offset, location.length));
+        }
+        if Self::looks_like_register(&name) {
+            return Err(line.error(AssemblerErrorKind::AmbiguousLabel(name), offset, location.length));
+        }
+        if let Some((_, previous)) = self.labels.get(&name) {
+            return Err(line.error(
+                AssemblerErrorKind::DuplicateSymbol {
+                    name,
+                    previous: format!("{}:{}", previous.path, previous.line),
+                },
+                offset,
+                location.length,
+            ));
+        }
+
+        self.labels.insert(name, (symbol, location));
+        Ok(())
+    }
+
+    /// `.equ NAME, expr` (or `.set`): defines a constant usable in any
+    /// expression in this file, or in other files if it is made `.global`.
+    fn define_constant(&mut self, line: &Line, code: &str, offset: usize) -> Result<(), AssemblerError> {
+        let directive_len = code.find(char::is_whitespace).unwrap_or(code.len());
+        let rest = &code[directive_len..];
+        let Some(comma) = rest.find(',') else {
+            return Err(line.error(
+                AssemblerErrorKind::MissingOperands(code[..directive_len].to_string()),
+                offset,
+                directive_len,
+            ));
+        };
+
+        let name = rest[..comma].trim();
+        let name_offset = offset + directive_len + rest.find(name).unwrap_or(0);
+        let value_text = &rest[comma + 1..];
+        let value = Token {
+            text: value_text.trim().to_string(),
+            offset: offset + directive_len + comma + 1 + (value_text.len() - value_text.trim_start().len()),
+        };
+
+        let expr = parse_expression(&value.text).map_err(|kind| line.token_error(kind, &value))?;
+        self.define_symbol(line, name.to_string(), name_offset, Symbol::Constant(Rc::new(expr)))
+    }
+
+    fn assemble_line(&mut self, line: &Line) -> Result<(), AssemblerError> {
+        let mut offset = line.code.len() - line.code.trim_start().len();
+        let mut code = line.code.trim();
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        let directive = code.split_whitespace().next().unwrap_or(code);
+        if directive == ".data" || directive == ".text" {
+            Self::check_operand_count(line, &Self::parse_instruction_parts(code, offset), 0)?;
+            self.current_section = if directive == ".data" { Section::Data } else { Section::Text };
+            return Ok(());
+        }
+
+        if directive == ".start" {
+            let parts = Self::parse_instruction_parts(code, offset);
+            Self::check_operand_count(line, &parts, 1)?;
+            if parts.len() >= 2 {
+                let location = line.location(parts[1].offset, parts[1].text.len());
+                self.start_label = Some((parts[1].text.clone(), location));
             }
+            return Ok(());
+        }
 
-            if self.current_section == Section::Data {
-                if line.starts_with(".ascii") || line.starts_with(".string") {
-                    let start = line.find('"').ok_or("Missing opening quote")?;
-                    let end = line.rfind('"').ok_or("Missing closing quote")?;
-                    if start >= end {
-                        return Err("Invalid string format".to_string());
-                    }
-                    let string_content = &line[start + 1..end];
-                    let processed = string_content
-                        .replace("\\n", "\n")
-                        .replace("\\t", "\t")
-                        .replace("\\r", "\r")
-                        .replace("\\\\", "\\")
-                        .replace("\\\"", "\"");
-
-                    self.data_section.extend(processed.bytes());
-                    if line.starts_with(".string") {
-                        self.data_section.push(0);
-                    }
-                    continue;
-                }
+        if matches!(code.split_whitespace().next(), Some(".equ" | ".set")) {
+            return self.define_constant(line, code, offset);
+        }
 
-                if line.starts_with(".byte") {
-                    let parts: Vec<&str> = line.split_whitespace().collect();
-                    for part in &parts[1..] {
-                        let byte_val = part
-                            .trim_end_matches(',')
-                            .parse::<u8>()
-                            .map_err(|_| format!("Invalid byte value: {}", part))?;
-                        self.data_section.push(byte_val);
-                    }
-                    continue;
+        if code.starts_with(".global") || code.starts_with(".globl") || code.starts_with(".extern") {
+            let parts = Self::parse_instruction_parts(code, offset);
+            for name in &parts[1..] {
+                let location = line.location(name.offset, name.text.len());
+                if parts[0].text == ".extern" {
+                    self.externs.insert(name.text.clone(), location);
+                } else {
+                    self.globals.push((name.text.clone(), location));
                 }
+            }
+            return Ok(());
+        }
 
-                if line.starts_with(".word") {
-                    let parts: Vec<&str> = line.split_whitespace().collect();
-                    for part in &parts[1..] {
-                        let word_val = part
-                            .trim_end_matches(',')
-                            .parse::<i32>()
-                            .map_err(|_| format!("Invalid word value: {}", part))?;
-                        self.data_section.extend_from_slice(&word_val.to_le_bytes());
-                    }
-                    continue;
+        if let Some(colon_pos) = code.find(':') {
+            let (label_part, code_part) = code.split_at(colon_pos);
+            let label = label_part.trim().to_string();
+
+            if !label.contains(char::is_whitespace) {
+                self.define_label(line, label, offset)?;
+
+                let rest = &code_part[1..];
+                offset += colon_pos + 1 + (rest.len() - rest.trim_start().len());
+                code = rest.trim();
+                if code.is_empty() {
+                    return Ok(());
                 }
-                continue;
             }
+        }
 
-            let parts = Self::parse_instruction_parts(line);
-            if parts.is_empty() {
-                continue;
-            }
+        if self.current_section == Section::Data {
+            return self.assemble_data(line, code, offset);
+        }
 
-            let opcode_str = parts[0].to_uppercase();
-            let opcode = match opcode_str.to_uppercase().as_str() {
-                "MOV" => OpCode::MOV,
-                "ADD" => OpCode::ADD,
-                "SUB" => OpCode::SUB,
-                "MUL" => OpCode::MUL,
-                "DIV" => OpCode::DIV,
-                "CMP" => OpCode::CMP,
-                "JMP" => OpCode::JMP,
-                "JEQ" => OpCode::JEQ,
-                "JNE" => OpCode::JNE,
-                "JLT" => OpCode::JLT,
-                "JGT" => OpCode::JGT,
-                "LEA" => OpCode::LEA,
-                "STO" => OpCode::STORE,
-                "PUS" => OpCode::PUSH,
-                "POP" => OpCode::POP,
-                "CAL" => OpCode::CALL,
-                "RET" => OpCode::RET,
-                "HLT" => OpCode::HALT,
-                "NOP" => OpCode::NOP,
-                "AND" => OpCode::AND,
-                "OR" => OpCode::OR,
-                "XOR" => OpCode::XOR,
-                "NOT" => OpCode::NOT,
-                "LSL" => OpCode::LSL,
-                "LSR" => OpCode::LSR,
-                "PRT" => OpCode::PRINT,
-                "PRC" => OpCode::PRINTC,
-                "INP" => OpCode::INPUT,
-                _ => {
-                    return Err(format!(
-                        "Unknown opcode: {} at line {}",
-                        opcode_str,
-                        line_num + 1
-                    ));
-                }
-            };
+        self.assemble_instruction(line, code, offset)
+    }
 
-            let mut instruction = Instruction {
-                opcode,
-                rd: 0,
-                rs1: 0,
-                rs2: 0,
-                immediate: 0,
-                label: None,
-            };
+    fn assemble_data(&mut self, line: &Line, code: &str, offset: usize) -> Result<(), AssemblerError> {
+        let directive_len = code.find(char::is_whitespace).unwrap_or(code.len());
+        if matches!(&code[..directive_len], ".ascii" | ".string") {
+            return self.assemble_strings(line, code, offset, directive_len);
+        }
 
-            match opcode {
-                OpCode::MOV => {
-                    if parts.len() >= 3 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        if let Some(imm) = Self::parse_immediate(&parts[2]) {
-                            instruction.immediate = imm;
-                        } else if let Some(reg) = Self::parse_register(&parts[2]) {
-                            instruction.rs1 = reg;
-                        } else {
-                            instruction.label = Some(parts[2].clone());
-                        }
-                    } else {
-                        return Err(format!("MOV instruction requires comma-separated operands at line {}", line_num + 1));
-                    }
-                }
-                OpCode::ADD
-                | OpCode::SUB
-                | OpCode::MUL
-                | OpCode::DIV
-                | OpCode::AND
-                | OpCode::OR
-                | OpCode::XOR => {
-                    if parts.len() >= 4 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        instruction.rs1 = Self::parse_register(&parts[2])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[2]))?;
-                        if let Some(imm) = Self::parse_immediate(&parts[3]) {
-                            instruction.immediate = imm;
-                        } else if let Some(reg) = Self::parse_register(&parts[3]) {
-                            instruction.rs2 = reg;
-                        }
-                    } else {
-                        return Err(format!("Instruction {} requires comma-separated operands at line {}", opcode_str, line_num + 1));
-                    }
+        let parts = Self::parse_instruction_parts(code, offset);
+        let directive = &parts[0];
+        let operands = &parts[1..];
+        let max_operands = match directive.text.as_str() {
+            ".space" | ".zero" => 2,
+            ".align" | ".incbin" => 1,
+            ".fill" => 3,
+            _ => usize::MAX - 1,
+        };
+        Self::check_operand_count(line, &parts, max_operands)?;
+        let missing_operands =
+            || line.token_error(AssemblerErrorKind::MissingOperands(directive.text.clone()), directive);
+
+        match directive.text.as_str() {
+            ".byte" | ".half" | ".word" | ".dword" => {
+                let size = match directive.text.as_str() {
+                    ".byte" => 1,
+                    ".half" => 2,
+                    ".word" => 4,
+                    _ => 8,
+                };
+                self.align_data(size);
+                for part in operands {
+                    self.data_value(line, part, size)?;
                 }
-                OpCode::CMP => {
-                    if parts.len() >= 3 {
-                        instruction.rs1 = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        if let Some(imm) = Self::parse_immediate(&parts[2]) {
-                            instruction.immediate = imm;
-                        } else if let Some(reg) = Self::parse_register(&parts[2]) {
-                            instruction.rs2 = reg;
-                        }
-                    } else {
-                        return Err(format!("CMP instruction requires comma-separated operands at line {}", line_num + 1));
-                    }
+            }
+            ".space" | ".zero" => {
+                let count = operands.first().ok_or_else(missing_operands)?;
+                let count = self.constant(line, count, Range::DATA_SIZE)?;
+                let fill = match operands.get(1) {
+                    Some(fill) => self.constant(line, fill, Range::BYTE)?,
+                    None => 0,
+                };
+                self.data_section.resize(self.data_section.len() + count as usize, fill as u8);
+            }
+            ".align" => {
+                let alignment = operands.first().ok_or_else(missing_operands)?;
+                let value = self.constant(line, alignment, Range::ALIGNMENT)?;
+                if !(value as u64).is_power_of_two() {
+                    return Err(line.token_error(
+                        AssemblerErrorKind::InvalidData(format!("alignment {} is not a power of two", value)),
+                        alignment,
+                    ));
                 }
-                OpCode::JMP
-                | OpCode::JEQ
-                | OpCode::JNE
-                | OpCode::JLT
-                | OpCode::JGT
-                | OpCode::CALL => {
-                    if parts.len() >= 2 {
-                        if let Some(imm) = Self::parse_immediate(&parts[1]) {
-                            instruction.immediate = imm;
-                        } else {
-                            instruction.label = Some(parts[1].clone());
+                self.align_data(value as usize);
+            }
+            ".fill" => {
+                let count = operands.first().ok_or_else(missing_operands)?;
+                let count = self.constant(line, count, Range::DATA_SIZE)?;
+                let size = match operands.get(1) {
+                    Some(size) => match self.constant(line, size, Range::DWORD)? {
+                        value @ (1 | 2 | 4 | 8) => value as usize,
+                        value => {
+                            return Err(line.token_error(
+                                AssemblerErrorKind::InvalidData(format!("fill size {} is not 1, 2, 4 or 8", value)),
+                                size,
+                            ));
                         }
-                    }
+                    },
+                    None => 1,
+                };
+                if count as usize * size > Range::DATA_SIZE.max() as usize {
+                    return Err(line.token_error(
+                        AssemblerErrorKind::InvalidData(format!("{} bytes of fill is too large", count as usize * size)),
+                        &operands[0],
+                    ));
                 }
-                OpCode::LEA | OpCode::STORE => {
-                    if parts.len() >= 3 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        let addr_part = parts[2].trim_start_matches('[').trim_end_matches(']');
-                        if let Some(reg) = Self::parse_register(addr_part) {
-                            instruction.rs1 = reg;
-                        } else if let Some(imm) = Self::parse_immediate(addr_part) {
-                            instruction.immediate = imm;
-                        } else {
-                            instruction.label = Some(addr_part.to_string());
+                self.align_data(size);
+                match operands.get(2) {
+                    Some(value) => {
+                        for _ in 0..count {
+                            self.data_value(line, value, size)?;
                         }
-                    } else {
-                        return Err(format!("Instruction {} requires comma-separated operands at line {}", opcode_str, line_num + 1));
-                    }
-                }
-                OpCode::PUSH | OpCode::POP | OpCode::PRINT | OpCode::PRINTC | OpCode::INPUT => {
-                    if parts.len() >= 2 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                    }
-                }
-                OpCode::NOT => {
-                    if parts.len() >= 3 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        instruction.rs1 = Self::parse_register(&parts[2])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[2]))?;
-                    } else {
-                        return Err(format!("NOT instruction requires comma-separated operands at line {}", line_num + 1));
-                    }
-                }
-                OpCode::LSL | OpCode::LSR => {
-                    if parts.len() >= 4 {
-                        instruction.rd = Self::parse_register(&parts[1])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[1]))?;
-                        instruction.rs1 = Self::parse_register(&parts[2])
-                            .ok_or_else(|| format!("Invalid register: {}", parts[2]))?;
-                        instruction.immediate = Self::parse_immediate(&parts[3])
-                            .ok_or_else(|| format!("Invalid immediate: {}", parts[3]))?;
-                    } else {
-                        return Err(format!("Instruction {} requires comma-separated operands at line {}", opcode_str, line_num + 1));
                     }
+                    None => self.data_section.resize(self.data_section.len() + count as usize * size, 0),
                 }
-                _ => {}
             }
+            ".incbin" => {
+                let path = operands.first().ok_or_else(missing_operands)?;
+                let last = &operands[operands.len() - 1];
+                let argument = &code[path.offset - offset..last.offset - offset + last.text.len()];
+                let relative = argument.strip_prefix('"').and_then(|rest| rest.strip_suffix('"'));
+                let Some(relative) = relative else {
+                    return Err(line.token_error(
+                        AssemblerErrorKind::InvalidInclude {
+                            path: argument.to_string(),
+                            reason: "expected a quoted path".to_string(),
+                        },
+                        path,
+                    ));
+                };
+                let full_path = Path::new(&*line.path).parent().unwrap_or(Path::new("")).join(relative);
+                let bytes = fs::read(&full_path).map_err(|e| {
+                    line.error(
+                        AssemblerErrorKind::InvalidInclude {
+                            path: full_path.display().to_string(),
+                            reason: e.to_string(),
+                        },
+                        path.offset,
+                        argument.len(),
+                    )
+                })?;
+                self.data_section.extend_from_slice(&bytes);
+            }
+            name if name.starts_with('.') => {
+                return Err(line.token_error(AssemblerErrorKind::UnknownDirective(name.to_string()), directive));
+            }
+            name => {
+                return Err(line.token_error(AssemblerErrorKind::InstructionInData(name.to_string()), directive));
+            }
+        }
+
+        Ok(())
+    }
 
-            self.instructions.push(instruction);
+    /// `.ascii` and `.string`: one or more comma-separated string literals.
+    /// `.string` terminates each of them with a zero byte.
+    fn assemble_strings(&mut self, line: &Line, code: &str, offset: usize, directive_len: usize) -> Result<(), AssemblerError> {
+        let terminate = &code[..directive_len] == ".string";
+        let invalid = |reason: &str, at: usize, length: usize| {
+            line.error(AssemblerErrorKind::InvalidString(reason.to_string()), offset + at, length)
+        };
+
+        let mut position = directive_len;
+        loop {
+            position += code[position..].len() - code[position..].trim_start().len();
+            if !code[position..].starts_with('"') {
+                let length = code[position..].find([',', ' ', '\t']).unwrap_or(code.len() - position);
+                return Err(invalid("expected a string in double quotes", position, length.max(1)));
+            }
+
+            let (bytes, length) = parse_string_literal(&code[position..])
+                .map_err(|error| invalid(&error.reason, position + error.offset, error.length))?;
+            self.data_section.extend_from_slice(&bytes);
+            if terminate {
+                self.data_section.push(0);
+            }
+            position += length;
+
+            let rest = code[position..].trim_start();
+            position = code.len() - rest.len();
+            match rest.chars().next() {
+                None => return Ok(()),
+                Some(',') => position += 1,
+                Some(_) => return Err(invalid("expected ',' between strings", position, 1)),
+            }
         }
+    }
 
-        for instruction in &mut self.instructions {
-            if let Some(label_name) = &instruction.label {
-                if let Some(&addr) = self.labels.get(label_name) {
-                    instruction.immediate = addr as i32;
-                } else {
-                    return Err(format!("Undefined label: {}", label_name));
-                }
+    /// Pads the data section with zeros to a multiple of `alignment`. Labels
+    /// at the current end move along, so in `value: .word 1` the label names
+    /// the aligned word rather than the padding.
+    fn align_data(&mut self, alignment: usize) {
+        self.data_alignment = self.data_alignment.max(alignment);
+        let end = self.data_section.len();
+        let aligned = end.next_multiple_of(alignment);
+        if aligned == end {
+            return;
+        }
+        for (symbol, _) in self.labels.values_mut() {
+            if let Symbol::Data(offset) = symbol
+                && *offset == end
+            {
+                *offset = aligned;
             }
         }
+        self.data_section.resize(aligned, 0);
+    }
+
+    /// Appends a `size`-byte data item, relocated at link time if it refers
+    /// to a label.
+    fn data_value(&mut self, line: &Line, token: &Token, size: usize) -> Result<(), AssemblerError> {
+        let range = match size {
+            1 => Range::BYTE,
+            2 => Range::HALF,
+            4 => Range::WORD,
+            _ => Range::DWORD,
+        };
+        let field = Field::Data {
+            offset: self.data_section.len(),
+            size,
+        };
+        let value = self.expression_value(line, token, &token.text, field, range)?;
+        self.data_section.extend_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
 
-        let mut bytecode = Vec::new();
-        bytecode.extend_from_slice(&self.data_section);
+    /// Evaluates an expression that has to be known while assembling, such as
+    /// a `.space` size, so it may only use constants defined before it.
+    fn constant(&self, line: &Line, token: &Token, range: Range) -> Result<i64, AssemblerError> {
+        let expr = parse_expression(&token.text).map_err(|kind| line.token_error(kind, token))?;
+        let value = match expr.evaluate(&LocalSymbols(&self.labels), 0) {
+            Ok(value) => value,
+            Err(EvalError::Invalid(kind)) => return Err(line.token_error(kind, token)),
+            Err(EvalError::Unresolved) => {
+                let reason = "the value must only use constants defined before it".to_string();
+                return Err(line.token_error(AssemblerErrorKind::InvalidExpression(reason), token));
+            }
+        };
+        range.check(value).map_err(|kind| line.token_error(kind, token))
+    }
 
-        while bytecode.len() % 8 != 0 {
-            bytecode.push(0);
+    /// Rejects a statement with more than `max` operands, pointing at the
+    /// extra ones.
+    fn check_operand_count(line: &Line, parts: &[Token], max: usize) -> Result<(), AssemblerError> {
+        if parts.len() <= max + 1 {
+            return Ok(());
         }
+        let first = &parts[max + 1];
+        let last = &parts[parts.len() - 1];
+        Err(line.error(
+            AssemblerErrorKind::TooManyOperands {
+                name: parts[0].text.clone(),
+                expected: max,
+                found: parts.len() - 1,
+            },
+            first.offset,
+            last.offset + last.text.len() - first.offset,
+        ))
+    }
 
-        let start_pc = if let Some(ref start) = self.start_label {
-            self.labels.get(start).copied().unwrap_or(0)
-        } else {
-            0
+    fn assemble_instruction(&mut self, line: &Line, code: &str, offset: usize) -> Result<(), AssemblerError> {
+        let parts = Self::parse_instruction_parts(code, offset);
+        if parts.is_empty() {
+            return Ok(());
+        }
+
+        let opcode_str = parts[0].text.to_uppercase();
+        let opcode = match opcode_str.as_str() {
+            "MOV" => OpCode::MOV,
+            "ADD" => OpCode::ADD,
+            "SUB" => OpCode::SUB,
+            "MUL" => OpCode::MUL,
+            "DIV" => OpCode::DIV,
+            "CMP" => OpCode::CMP,
+            "JMP" => OpCode::JMP,
+            "JEQ" => OpCode::JEQ,
+            "JNE" => OpCode::JNE,
+            "JLT" => OpCode::JLT,
+            "JGT" => OpCode::JGT,
+            "JLE" => OpCode::JLE,
+            "JGE" => OpCode::JGE,
+            "JCS" => OpCode::JCS,
+            "JCC" => OpCode::JCC,
+            "JVS" => OpCode::JVS,
+            "JVC" => OpCode::JVC,
+            "JA" => OpCode::JA,
+            "JB" => OpCode::JB,
+            "LEA" => OpCode::LEA,
+            "LOAD" | "LDR" => OpCode::LOAD,
+            "LOADB" | "LDB" => OpCode::LOADB,
+            "STORE" | "STO" => OpCode::STORE,
+            "PUSH" | "PUS" => OpCode::PUSH,
+            "POP" => OpCode::POP,
+            "CALL" | "CAL" => OpCode::CALL,
+            "RET" => OpCode::RET,
+            "HALT" | "HLT" => OpCode::HALT,
+            "NOP" => OpCode::NOP,
+            "AND" => OpCode::AND,
+            "OR" => OpCode::OR,
+            "XOR" => OpCode::XOR,
+            "NOT" => OpCode::NOT,
+            "LSL" => OpCode::LSL,
+            "LSR" => OpCode::LSR,
+            "PRINT" | "PRT" => OpCode::PRINT,
+            "PRINTC" | "PRC" => OpCode::PRINTC,
+            "INPUT" | "INP" => OpCode::INPUT,
+            _ if parts[0].text.starts_with('.') => {
+                let name = parts[0].text.clone();
+                return Err(line.token_error(AssemblerErrorKind::UnknownDirective(name), &parts[0]));
+            }
+            _ => {
+                return Err(line.token_error(AssemblerErrorKind::UnknownOpcode(opcode_str), &parts[0]));
+            }
         };
 
-        bytecode.extend_from_slice(&(start_pc as u32).to_le_bytes());
-        bytecode.extend_from_slice(&[0u8; 4]);
+        let signature = Signature::of(opcode);
+        let operands = &parts[1..];
+        if operands.len() < signature.required {
+            return Err(line.token_error(AssemblerErrorKind::MissingOperands(opcode_str), &parts[0]));
+        }
+        Self::check_operand_count(line, &parts, signature.operands.len())?;
+        for (token, &kind) in operands.iter().zip(signature.operands) {
+            kind.check(line, token)?;
+        }
+
+        let mut instruction = Instruction {
+            opcode,
+            rd: 0,
+            rs1: 0,
+            rs2: 0,
+            immediate: 0,
+            mode: OperandMode::Register,
+        };
 
-        for instruction in &self.instructions {
-            bytecode.push(instruction.opcode as u8);
-            bytecode.push(instruction.rd);
-            bytecode.push(instruction.rs1);
-            bytecode.push(instruction.rs2);
-            bytecode.extend_from_slice(&instruction.immediate.to_le_bytes());
+        // Operand kinds and counts were checked against the signature above, so
+        // each arm can rely on them.
+        match opcode {
+            OpCode::MOV => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                if let Some(reg) = Self::parse_register(&operands[1].text) {
+                    instruction.rs1 = reg;
+                } else {
+                    instruction.immediate = self.immediate(line, &operands[1], Range::IMMEDIATE)?;
+                    instruction.mode = OperandMode::Immediate;
+                }
+            }
+            OpCode::ADD
+            | OpCode::SUB
+            | OpCode::MUL
+            | OpCode::DIV
+            | OpCode::AND
+            | OpCode::OR
+            | OpCode::XOR => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                instruction.rs1 = Self::register(line, &operands[1])?;
+                if let Some(reg) = Self::parse_register(&operands[2].text) {
+                    instruction.rs2 = reg;
+                } else {
+                    instruction.immediate = self.immediate(line, &operands[2], Range::IMMEDIATE)?;
+                    instruction.mode = OperandMode::Immediate;
+                }
+            }
+            OpCode::CMP => {
+                instruction.rs1 = Self::register(line, &operands[0])?;
+                if let Some(reg) = Self::parse_register(&operands[1].text) {
+                    instruction.rs2 = reg;
+                } else {
+                    instruction.immediate = self.immediate(line, &operands[1], Range::IMMEDIATE)?;
+                    instruction.mode = OperandMode::Immediate;
+                }
+            }
+            _ if opcode.is_branch() => {
+                instruction.immediate = self.immediate(line, &operands[0], Range::IMMEDIATE)?;
+            }
+            OpCode::LEA | OpCode::STORE | OpCode::LOAD | OpCode::LOADB => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                self.memory_operand(line, &operands[1], &mut instruction)?;
+            }
+            OpCode::PUSH | OpCode::POP | OpCode::PRINT | OpCode::PRINTC => {
+                instruction.rd = Self::register(line, &operands[0])?;
+            }
+            OpCode::INPUT => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                if let Some(reg) = Self::parse_register(&operands[1].text) {
+                    instruction.rs1 = reg;
+                } else {
+                    instruction.immediate = self.immediate(line, &operands[1], Range::INPUT_MODE)?;
+                    instruction.mode = OperandMode::Immediate;
+                    // A known mode says whether the buffer register is needed,
+                    // so it is neither left to default to r0 nor ignored.
+                    if instruction.immediate == 2 && operands.len() < 3 {
+                        return Err(line.token_error(AssemblerErrorKind::MissingOperands(opcode_str), &parts[0]));
+                    }
+                    if instruction.immediate != 2 {
+                        Self::check_operand_count(line, &parts, 2)?;
+                    }
+                }
+                if let Some(buffer) = operands.get(2) {
+                    instruction.rs2 = Self::register(line, buffer)?;
+                }
+            }
+            OpCode::NOT => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                instruction.rs1 = Self::register(line, &operands[1])?;
+            }
+            OpCode::LSL | OpCode::LSR => {
+                instruction.rd = Self::register(line, &operands[0])?;
+                instruction.rs1 = Self::register(line, &operands[1])?;
+                instruction.immediate = self.immediate(line, &operands[2], Range::SHIFT)?;
+            }
+            _ => {}
         }
 
-        Ok(bytecode)
+        self.instructions.push(instruction);
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssemblerErrorKind {
+    UnknownOpcode(String),
+    InvalidRegister(String),
+    InvalidImmediate(String),
+    InvalidMemoryOperand(String),
+    MissingOperands(String),
+    UndefinedLabel(String),
+    AmbiguousLabel(String),
+    InvalidSymbolName(String),
+    DuplicateSymbol { name: String, previous: String },
+    MissingExtern(String),
+    UndefinedGlobal(String),
+    DuplicateStart,
+    InvalidString(String),
+    InvalidData(String),
+    InvalidMacro(String),
+    UnterminatedMacro(String),
+    UnexpectedEndm,
+    MacroArguments { name: String, expected: usize, found: usize },
+    MacroRecursionLimit { name: String, limit: usize },
+    InvalidExpression(String),
+    ValueOutOfRange { value: i64, min: i64, max: i64 },
+    CircularConstant(String),
+    InvalidInclude { path: String, reason: String },
+    CircularInclude(String),
+    InvalidConditional(String),
+    UnterminatedConditional,
+    UnknownDirective(String),
+    InstructionInData(String),
+    UnterminatedComment,
+    TooManyOperands { name: String, expected: usize, found: usize },
+    RegisterOutOfRange(String),
+    UnexpectedOperand { expected: String, found: String },
+}
+
+impl fmt::Display for AssemblerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblerErrorKind::UnknownOpcode(opcode) => write!(f, "Unknown opcode: {}", opcode),
+            AssemblerErrorKind::InvalidRegister(reg) => write!(f, "Invalid register: {}", reg),
+            AssemblerErrorKind::InvalidImmediate(imm) => write!(f, "Invalid immediate: {}", imm),
+            AssemblerErrorKind::InvalidMemoryOperand(operand) => {
+                write!(f, "Invalid memory operand: {}", operand)
+            }
+            AssemblerErrorKind::MissingOperands(opcode) => {
+                write!(f, "{} is missing operands", opcode)
+            }
+            AssemblerErrorKind::UndefinedLabel(label) => write!(f, "Undefined label: {}", label),
+            AssemblerErrorKind::AmbiguousLabel(label) => {
+                write!(f, "Label name {} is ambiguous: it is also a register name", label)
+            }
+            AssemblerErrorKind::InvalidSymbolName(name) => {
+                write!(f, "Invalid symbol name '{}': use letters, digits, '_' and '.', not starting with a digit", name)
+            }
+            AssemblerErrorKind::DuplicateSymbol { name, previous } => {
+                write!(f, "Duplicate symbol: {} (first defined at {})", name, previous)
+            }
+            AssemblerErrorKind::MissingExtern(name) => write!(
+                f,
+                "Undefined label: {} is defined in another file; declare it with .extern",
+                name
+            ),
+            AssemblerErrorKind::UndefinedGlobal(name) => {
+                write!(f, "Symbol {} is declared .global but never defined", name)
+            }
+            AssemblerErrorKind::DuplicateStart => {
+                write!(f, "Multiple .start directives; a program has one entry point")
+            }
+            AssemblerErrorKind::InvalidString(reason) => write!(f, "Invalid string: {}", reason),
+            AssemblerErrorKind::InvalidData(value) => write!(f, "Invalid data value: {}", value),
+            AssemblerErrorKind::InvalidMacro(reason) => write!(f, "Invalid macro definition: {}", reason),
+            AssemblerErrorKind::UnterminatedMacro(name) => {
+                write!(f, "Macro {} is missing its .endm", name)
+            }
+            AssemblerErrorKind::UnexpectedEndm => write!(f, ".endm without a matching .macro"),
+            AssemblerErrorKind::MacroArguments { name, expected, found } => write!(
+                f,
+                "Macro {} takes {} argument(s) but {} were given",
+                name, expected, found
+            ),
+            AssemblerErrorKind::MacroRecursionLimit { name, limit } => write!(
+                f,
+                "Expanding macro {} exceeds the nesting limit of {} (is it recursive?)",
+                name, limit
+            ),
+            AssemblerErrorKind::InvalidExpression(reason) => write!(f, "Invalid expression: {}", reason),
+            AssemblerErrorKind::ValueOutOfRange { value, min, max } => {
+                write!(f, "Value {} is out of range (expected {} to {})", value, min, max)
+            }
+            AssemblerErrorKind::CircularConstant(name) => {
+                write!(f, "Constant {} is defined in terms of itself", name)
+            }
+            AssemblerErrorKind::InvalidInclude { path, reason } => {
+                write!(f, "Cannot include {}: {}", path, reason)
+            }
+            AssemblerErrorKind::CircularInclude(chain) => write!(f, "Circular include: {}", chain),
+            AssemblerErrorKind::InvalidConditional(reason) => write!(f, "Invalid conditional: {}", reason),
+            AssemblerErrorKind::UnterminatedConditional => write!(f, "Conditional block is missing its .endif"),
+            AssemblerErrorKind::UnknownDirective(name) => write!(f, "Unknown directive: {}", name),
+            AssemblerErrorKind::InstructionInData(name) => {
+                write!(f, "Instruction {} is in the .data section; switch to .text first", name)
+            }
+            AssemblerErrorKind::UnterminatedComment => write!(f, "/* comment is never closed with */"),
+            AssemblerErrorKind::TooManyOperands { name, expected: 0, found } => {
+                write!(f, "{} takes no operands but {} were given", name, found)
+            }
+            AssemblerErrorKind::TooManyOperands { name, expected, found } => write!(
+                f,
+                "{} takes at most {} operand(s) but {} were given",
+                name, expected, found
+            ),
+            AssemblerErrorKind::RegisterOutOfRange(reg) => {
+                write!(f, "Register {} does not exist (registers are r0 to r31)", reg)
+            }
+            AssemblerErrorKind::UnexpectedOperand { expected, found } => {
+                write!(f, "Expected {} but found {}", expected, found)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AssemblerError {
+    pub kind: AssemblerErrorKind,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+    pub source_line: String,
+    /// Extra context, such as the macro invocations that produced this line.
+    pub notes: Vec<String>,
+}
+
+impl AssemblerError {
+    pub fn new(
+        kind: AssemblerErrorKind,
+        file: String,
+        line: usize,
+        column: usize,
+        length: usize,
+        source_line: String,
+    ) -> Self {
+        AssemblerError {
+            kind,
+            file,
+            line,
+            column,
+            length,
+            source_line,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let source_line = self.source_line.replace('\t', " ");
+
+        writeln!(f, "Assembler Error: {}", self.kind)?;
+        writeln!(f, "{}--> {}:{}:{}", gutter, self.file, self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, source_line)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(self.column.saturating_sub(1)),
+            "^".repeat(self.length.max(1))
+        )?;
+        for note in &self.notes {
+            write!(f, "\n{} = note: {}", gutter, note)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AssemblerError {}
@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use crate::fam::{FamImage, FAM_VERSION};
+use crate::op_codes::OpCode;
+use crate::runtime::{Instruction, OperandMode};
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Turns .fam bytecode back into assembly source.
+///
+/// Jump and call targets (and the entry point) get synthesized `L<index>`
+/// labels. Data references are printed as plain addresses, so assembling the
+/// output produces the original bytes. The exception is a text word that is
+/// not a valid instruction: the assembler cannot produce one, so it is shown
+/// as an `; invalid instruction` comment and reassembling drops its 8 bytes.
+pub fn disassemble(bytecode: &[u8]) -> Result<String, String> {
+    let image = FamImage::from_bytes(bytecode).map_err(|e| e.to_string())?;
+    if !image.text.len().is_multiple_of(8) {
+        return Err(format!(
+            "Truncated text section: {} bytes is not a whole number of 8-byte instructions",
+            image.text.len()
+        ));
+    }
+
+    let instructions: Vec<Option<Instruction>> = image
+        .text
+        .chunks_exact(8)
+        .map(|chunk| {
+            let (word1, word2) = Instruction::words_from_bytes(chunk);
+            Instruction::decode(word1, word2)
+        })
+        .collect();
+
+    let mut targets = BTreeSet::new();
+    if !instructions.is_empty() {
+        targets.insert(image.entry as usize);
+    }
+    for instruction in instructions.iter().flatten() {
+        if instruction.opcode.is_branch()
+            && instruction.immediate >= 0
+            && (instruction.immediate as usize) < instructions.len()
+        {
+            targets.insert(instruction.immediate as usize);
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "; Disassembled .fam (format version {})", FAM_VERSION).unwrap();
+    if !instructions.is_empty() {
+        writeln!(out, ".start {}", label_name(image.entry as usize)).unwrap();
+    }
+
+    if !image.data.is_empty() {
+        writeln!(out, "\n.data").unwrap();
+        disassemble_data(&image.data, &mut out);
+    }
+
+    writeln!(out, "\n.text").unwrap();
+    for (index, (instruction, bytes)) in instructions.iter().zip(image.text.chunks_exact(8)).enumerate() {
+        if targets.contains(&index) {
+            writeln!(out, "{}:", label_name(index)).unwrap();
+        }
+        match instruction {
+            Some(instruction) => {
+                writeln!(out, "    {}", format_instruction(instruction, instructions.len())).unwrap();
+            }
+            None => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(out, "    ; invalid instruction: {}", hex.join(" ")).unwrap();
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+#[inline]
+fn label_name(index: usize) -> String {
+    format!("L{:04}", index)
+}
+
+/// Whether `text` reads as a string: no control characters apart from the
+/// ones `.string` has short escapes for.
+#[inline]
+fn is_text(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| matches!(c, '\n' | '\t' | '\r') || !c.is_control())
+}
+
+/// Emits null-terminated UTF-8 text as `.string` and everything else as `.byte`.
+fn disassemble_data(data: &[u8], out: &mut String) {
+    let mut pending = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let Some(run) = data[i..].iter().position(|&b| b == 0) else {
+            pending.extend_from_slice(&data[i..]);
+            break;
+        };
+        let text = std::str::from_utf8(&data[i..i + run]).ok().filter(|text| is_text(text));
+        if let Some(text) = text {
+            flush_bytes(&mut pending, out);
+            let escaped: String = text
+                .chars()
+                .map(|c| match c {
+                    '\n' => "\\n".to_string(),
+                    '\t' => "\\t".to_string(),
+                    '\r' => "\\r".to_string(),
+                    '"' => "\\\"".to_string(),
+                    '\\' => "\\\\".to_string(),
+                    _ => c.to_string(),
+                })
+                .collect();
+            writeln!(out, "    .string \"{}\"", escaped).unwrap();
+            i += text.len() + 1;
+        } else {
+            pending.push(data[i]);
+            i += 1;
+        }
+    }
+
+    flush_bytes(&mut pending, out);
+}
+
+fn flush_bytes(pending: &mut Vec<u8>, out: &mut String) {
+    for line in pending.chunks(BYTES_PER_LINE) {
+        let values: Vec<String> = line.iter().map(|b| b.to_string()).collect();
+        writeln!(out, "    .byte {}", values.join(", ")).unwrap();
+    }
+    pending.clear();
+}
+
+fn format_instruction(instruction: &Instruction, code_len: usize) -> String {
+    let rd = format!("r{}", instruction.rd);
+    let rs1 = format!("r{}", instruction.rs1);
+    let imm = format!("#{}", instruction.immediate);
+    let immediate_mode = instruction.mode == OperandMode::Immediate;
+    let source2 = if immediate_mode {
+        imm.clone()
+    } else {
+        format!("r{}", instruction.rs2)
+    };
+
+    match instruction.opcode {
+        OpCode::MOV => {
+            let source = if immediate_mode { imm } else { rs1 };
+            format!("MOV {}, {}", rd, source)
+        }
+        OpCode::ADD
+        | OpCode::SUB
+        | OpCode::MUL
+        | OpCode::DIV
+        | OpCode::AND
+        | OpCode::OR
+        | OpCode::XOR => format!("{:?} {}, {}, {}", instruction.opcode, rd, rs1, source2),
+        OpCode::CMP => format!("CMP {}, {}", rs1, source2),
+        opcode if opcode.is_branch() => {
+            let target = if instruction.immediate >= 0 && (instruction.immediate as usize) < code_len {
+                label_name(instruction.immediate as usize)
+            } else {
+                imm
+            };
+            format!("{:?} {}", instruction.opcode, target)
+        }
+        OpCode::LEA | OpCode::STORE | OpCode::LOAD | OpCode::LOADB => {
+            let address = if immediate_mode {
+                format!("[{}]", imm)
+            } else if instruction.immediate == 0 {
+                format!("[{}]", rs1)
+            } else if instruction.immediate < 0 && instruction.immediate != i32::MIN {
+                format!("[{} - {}]", rs1, -instruction.immediate)
+            } else {
+                format!("[{} + {}]", rs1, instruction.immediate)
+            };
+            format!("{:?} {}, {}", instruction.opcode, rd, address)
+        }
+        OpCode::PUSH | OpCode::POP | OpCode::PRINT | OpCode::PRINTC => {
+            format!("{:?} {}", instruction.opcode, rd)
+        }
+        OpCode::INPUT => {
+            let input_mode = if immediate_mode { imm } else { rs1 };
+            if instruction.rs2 != 0 {
+                format!("INPUT {}, {}, r{}", rd, input_mode, instruction.rs2)
+            } else {
+                format!("INPUT {}, {}", rd, input_mode)
+            }
+        }
+        OpCode::NOT => format!("NOT {}, {}", rd, rs1),
+        OpCode::LSL | OpCode::LSR => format!("{:?} {}, {}, {}", instruction.opcode, rd, rs1, imm),
+        _ => format!("{:?}", instruction.opcode),
+    }
+}
@@ -0,0 +1,605 @@
+use crate::assembler_error::AssemblerErrorKind;
+use crate::runtime::DATA_BASE;
+
+/// A constant expression from an operand, data directive or `.equ`.
+///
+/// Operators, loosest binding first: `||`, `&&`, `|`, `^`, `&`, `== !=`,
+/// `< <= > >=`, `<< >>`, `+ -`, `* / %`, then unary `- + ~ !`. Comparisons
+/// and logical operators give 1 or 0. Operands are decimal, `0x` hex, `0b`
+/// binary and `0o` octal numbers, character literals such as `'A'` or
+/// `'\n'`, symbols (labels and constants) and parenthesized expressions.
+/// Arithmetic is done in 64 bits; the result is range-checked against the
+/// width of the field it is stored in.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(i64),
+    Symbol(String),
+    Unary(char, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Why an expression could not be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum EvalError {
+    /// It refers to a symbol whose value is not known yet, such as a label
+    /// before linking.
+    Unresolved,
+    Invalid(AssemblerErrorKind),
+}
+
+impl From<AssemblerErrorKind> for EvalError {
+    fn from(kind: AssemblerErrorKind) -> Self {
+        EvalError::Invalid(kind)
+    }
+}
+
+/// Looks up symbol values while evaluating an expression. `depth` counts
+/// constants currently being expanded, so implementations can stop cycles.
+pub(crate) trait Symbols {
+    fn value(&self, name: &str, depth: usize) -> Result<i64, EvalError>;
+}
+
+/// How many constants may refer to each other before a definition is treated
+/// as circular.
+pub(crate) const CONSTANT_DEPTH_LIMIT: usize = 64;
+
+/// The values a field can hold. Immediates accept both signed and unsigned
+/// 32-bit spellings, so `0xFFFFFFFF` and `-1` are the same word.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Range {
+    min: i64,
+    max: i64,
+}
+
+impl Range {
+    pub(crate) const IMMEDIATE: Range = Range::new(i32::MIN as i64, u32::MAX as i64);
+    pub(crate) const WORD: Range = Range::IMMEDIATE;
+    pub(crate) const BYTE: Range = Range::new(i8::MIN as i64, u8::MAX as i64);
+    pub(crate) const HALF: Range = Range::new(i16::MIN as i64, u16::MAX as i64);
+    pub(crate) const DWORD: Range = Range::new(i64::MIN, i64::MAX);
+    /// Byte counts for `.space` and `.fill`, far beyond any data memory the
+    /// VM is normally given but small enough to catch a runaway expression.
+    pub(crate) const DATA_SIZE: Range = Range::new(0, 1 << 24);
+    /// `.align` amounts; the data section base is only 512-byte aligned.
+    pub(crate) const ALIGNMENT: Range = Range::new(1, DATA_BASE as i64);
+    pub(crate) const SHIFT: Range = Range::new(0, 31);
+    pub(crate) const INPUT_MODE: Range = Range::new(0, 3);
+
+    pub(crate) const fn new(min: i64, max: i64) -> Self {
+        Range { min, max }
+    }
+
+    pub(crate) fn max(self) -> i64 {
+        self.max
+    }
+
+    /// Checks that `value` fits in the field.
+    pub(crate) fn check(self, value: i64) -> Result<i64, AssemblerErrorKind> {
+        if value < self.min || value > self.max {
+            return Err(AssemblerErrorKind::ValueOutOfRange {
+                value,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(value)
+    }
+}
+
+impl Expr {
+    pub(crate) fn evaluate(&self, symbols: &dyn Symbols, depth: usize) -> Result<i64, EvalError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Symbol(name) => symbols.value(name, depth),
+            Expr::Unary(op, operand) => {
+                let value = operand.evaluate(symbols, depth)?;
+                match op {
+                    '-' => value.checked_neg().ok_or_else(|| overflow().into()),
+                    '~' => Ok(!value),
+                    '!' => Ok((value == 0) as i64),
+                    _ => Ok(value),
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.evaluate(symbols, depth)?;
+                // `&&` and `||` short-circuit like C, so `.if DEBUG && LEVEL > 1`
+                // does not need LEVEL defined when DEBUG is 0.
+                match op {
+                    BinaryOp::LogicalAnd if left == 0 => return Ok(0),
+                    BinaryOp::LogicalOr if left != 0 => return Ok(1),
+                    _ => {}
+                }
+                let right = right.evaluate(symbols, depth)?;
+                let result = match op {
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Sub => left.checked_sub(right),
+                    BinaryOp::Mul => left.checked_mul(right),
+                    BinaryOp::Div | BinaryOp::Rem if right == 0 => {
+                        return Err(invalid("division by zero").into());
+                    }
+                    BinaryOp::Div => left.checked_div(right),
+                    BinaryOp::Rem => left.checked_rem(right),
+                    BinaryOp::Shl | BinaryOp::Shr if !(0..64).contains(&right) => {
+                        return Err(invalid(&format!("shift amount {} is out of range", right)).into());
+                    }
+                    BinaryOp::Shl => left.checked_shl(right as u32),
+                    BinaryOp::Shr => left.checked_shr(right as u32),
+                    BinaryOp::And => Some(left & right),
+                    BinaryOp::Or => Some(left | right),
+                    BinaryOp::Xor => Some(left ^ right),
+                    BinaryOp::Eq => Some((left == right) as i64),
+                    BinaryOp::Ne => Some((left != right) as i64),
+                    BinaryOp::Lt => Some((left < right) as i64),
+                    BinaryOp::Le => Some((left <= right) as i64),
+                    BinaryOp::Gt => Some((left > right) as i64),
+                    BinaryOp::Ge => Some((left >= right) as i64),
+                    BinaryOp::LogicalAnd => Some((left != 0 && right != 0) as i64),
+                    BinaryOp::LogicalOr => Some((left != 0 || right != 0) as i64),
+                };
+                result.ok_or_else(|| overflow().into())
+            }
+        }
+    }
+}
+
+#[inline]
+fn invalid(reason: &str) -> AssemblerErrorKind {
+    AssemblerErrorKind::InvalidExpression(reason.to_string())
+}
+
+#[inline]
+fn overflow() -> AssemblerErrorKind {
+    invalid("arithmetic overflow")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+}
+
+/// Longer operators come first so `<<` is not read as two `<`.
+const OPERATORS: [&str; 22] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+    "!", "(", ")",
+];
+
+pub(crate) fn parse_expression(text: &str) -> Result<Expr, AssemblerErrorKind> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens, position: 0 };
+    let expr = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(expr),
+        Some(token) => Err(invalid(&format!("unexpected {}", describe(token)))),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(value) => format!("number {}", value),
+        Token::Symbol(name) => format!("symbol {}", name),
+        Token::Operator(op) => format!("'{}'", op),
+    }
+}
+
+#[inline]
+fn is_symbol_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_' || ch == '.'
+}
+
+#[inline]
+fn is_symbol_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, AssemblerErrorKind> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_digit() {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            rest = &rest[len..];
+        } else if ch == '\'' {
+            let (value, len) = parse_char(rest)?;
+            tokens.push(Token::Number(value));
+            rest = &rest[len..];
+        } else if is_symbol_start(ch) {
+            let len = rest.find(|c: char| !is_symbol_char(c)).unwrap_or(rest.len());
+            let name = &rest[..len];
+            if is_register(name) {
+                return Err(invalid(&format!("register {} cannot be used in an expression", name)));
+            }
+            tokens.push(Token::Symbol(name.to_string()));
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(invalid(&format!("unexpected character '{}'", ch)));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+#[inline]
+fn is_register(name: &str) -> bool {
+    (name.starts_with('r') || name.starts_with('R')) && name[1..].parse::<u8>().is_ok()
+}
+
+fn parse_number(text: &str) -> Result<i64, AssemblerErrorKind> {
+    let digits = text.replace('_', "");
+    let lower = digits.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else if let Some(octal) = lower.strip_prefix("0o") {
+        i64::from_str_radix(octal, 8)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| invalid(&format!("invalid number {}", text)))
+}
+
+/// Parses a character literal at the start of `text`, returning its value and
+/// the number of bytes consumed. The value is the Unicode code point, or the
+/// byte for a `\xNN` escape.
+fn parse_char(text: &str) -> Result<(i64, usize), AssemblerErrorKind> {
+    let body = &text[1..];
+    let (value, len) = match body.chars().next() {
+        Some('\\') => match parse_escape(&body[1..]).map_err(|reason| invalid(&reason))? {
+            (Escaped::Char(c), len) => (c as i64, len + 1),
+            (Escaped::Byte(b), len) => (b as i64, len + 1),
+        },
+        Some('\'') | None => return Err(invalid("empty character literal")),
+        Some(c) => (c as i64, c.len_utf8()),
+    };
+    match body[len..].chars().next() {
+        Some('\'') => Ok((value, len + 2)),
+        _ => Err(invalid("unterminated character literal")),
+    }
+}
+
+/// What an escape sequence stands for: a character, stored as UTF-8 in
+/// strings, or a raw byte for `\xNN`.
+pub(crate) enum Escaped {
+    Char(char),
+    Byte(u8),
+}
+
+/// Decodes the escape sequence following a backslash at the start of `text`,
+/// returning it with its length in bytes. Supported: `\n \t \r \0 \a \b \f
+/// \v \\ \' \"`, `\xNN` (two hex digits) and `\u{N}` (1 to 6 hex digits).
+pub(crate) fn parse_escape(text: &str) -> Result<(Escaped, usize), String> {
+    let Some(c) = text.chars().next() else {
+        return Err("unterminated escape sequence".to_string());
+    };
+    let simple = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'a' => '\x07',
+        'b' => '\x08',
+        'f' => '\x0C',
+        'v' => '\x0B',
+        '\\' | '\'' | '"' => c,
+        'x' => {
+            let digits = text.get(1..3).filter(|d| d.chars().all(|c| c.is_ascii_hexdigit()));
+            return match digits.and_then(|d| u8::from_str_radix(d, 16).ok()) {
+                Some(byte) => Ok((Escaped::Byte(byte), 3)),
+                None => Err("\\x must be followed by two hex digits".to_string()),
+            };
+        }
+        'u' => {
+            let close = text.find('}');
+            let digits = text.strip_prefix("u{").zip(close).map(|(_, close)| &text[2..close]);
+            let Some(digits) = digits.filter(|d| (1..=6).contains(&d.len())) else {
+                return Err("\\u must be followed by 1 to 6 hex digits in braces, like \\u{e9}".to_string());
+            };
+            let code = u32::from_str_radix(digits, 16).map_err(|_| format!("invalid hex digits in \\u{{{}}}", digits))?;
+            return match char::from_u32(code) {
+                Some(c) => Ok((Escaped::Char(c), digits.len() + 3)),
+                None => Err(format!("\\u{{{}}} is not a valid Unicode character", digits)),
+            };
+        }
+        _ => return Err(format!("unknown escape \\{}", c)),
+    };
+    Ok((Escaped::Char(simple), 1))
+}
+
+/// Why a string literal is invalid, and which bytes of it to point at.
+pub(crate) struct StringError {
+    pub(crate) reason: String,
+    pub(crate) offset: usize,
+    pub(crate) length: usize,
+}
+
+/// Parses the double-quoted string literal at the start of `text` into bytes:
+/// text is encoded as UTF-8 and `\xNN` escapes give raw bytes. Returns the
+/// bytes and the length of the literal including its quotes.
+pub(crate) fn parse_string_literal(text: &str) -> Result<(Vec<u8>, usize), StringError> {
+    let mut bytes = Vec::new();
+    let mut i = 1;
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            '"' => return Ok((bytes, i + 1)),
+            '\\' => {
+                let (escaped, len) = parse_escape(&text[i + 1..]).map_err(|reason| StringError {
+                    reason,
+                    offset: i,
+                    length: text[i + 1..].chars().next().map_or(1, |c| 1 + c.len_utf8()),
+                })?;
+                match escaped {
+                    Escaped::Char(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    Escaped::Byte(b) => bytes.push(b),
+                }
+                i += 1 + len;
+            }
+            _ => {
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                i += c.len_utf8();
+            }
+        }
+    }
+    Err(StringError {
+        reason: "missing closing quote".to_string(),
+        offset: 0,
+        length: text.len(),
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+/// Binary operators by precedence level, loosest first.
+const LEVELS: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[("<", BinaryOp::Lt), ("<=", BinaryOp::Le), (">", BinaryOp::Gt), (">=", BinaryOp::Ge)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+impl Parser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, AssemblerErrorKind> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(op) = self.peek_operator()
+            && let Some(&(_, binary)) = LEVELS[level].iter().find(|(symbol, _)| *symbol == op)
+        {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(binary, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AssemblerErrorKind> {
+        match self.peek_operator() {
+            Some(op @ ("-" | "+" | "~" | "!")) => {
+                self.position += 1;
+                let operand = self.unary()?;
+                let op = op.chars().next().unwrap_or('+');
+                Ok(match (op, operand) {
+                    ('-', Expr::Number(value)) => Expr::Number(-value),
+                    ('+', operand) => operand,
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, AssemblerErrorKind> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Symbol(name)) => Ok(Expr::Symbol(name)),
+            Some(Token::Operator("(")) => {
+                let inner = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Operator(")")) => {
+                        self.position += 1;
+                        Ok(inner)
+                    }
+                    _ => Err(invalid("missing ')'")),
+                }
+            }
+            Some(token) => Err(invalid(&format!("unexpected {}", describe(&token)))),
+            None => Err(invalid("expected a value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    /// Constants for tests, looked up like the assembler's local symbols.
+    struct Constants(HashMap<&'static str, Expr>);
+
+    impl Symbols for Constants {
+        fn value(&self, name: &str, depth: usize) -> Result<i64, EvalError> {
+            match self.0.get(name) {
+                Some(_) if depth >= CONSTANT_DEPTH_LIMIT => {
+                    Err(AssemblerErrorKind::CircularConstant(name.to_string()).into())
+                }
+                Some(expr) => expr.evaluate(self, depth + 1),
+                None => Err(EvalError::Unresolved),
+            }
+        }
+    }
+
+    fn constants(definitions: &[(&'static str, &str)]) -> Constants {
+        Constants(
+            definitions
+                .iter()
+                .map(|&(name, text)| (name, parse_expression(text).unwrap()))
+                .collect(),
+        )
+    }
+
+    fn eval(text: &str) -> Result<i64, EvalError> {
+        parse_expression(text).map_err(EvalError::Invalid)?.evaluate(&constants(&[]), 0)
+    }
+
+    fn invalid_reason(text: &str) -> String {
+        match eval(text) {
+            Err(EvalError::Invalid(AssemblerErrorKind::InvalidExpression(reason))) => reason,
+            other => panic!("expected an invalid expression for {:?}, got {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("6 & 3 | 8"), Ok(10));
+        assert_eq!(eval("1 | 2 ^ 3"), Ok(1));
+        assert_eq!(eval("1 + 1 == 2 && 3 < 2 || 5 >= 5"), Ok(1));
+        assert_eq!(eval("7 % 4 * 2"), Ok(6));
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(parse_expression("-5"), Ok(Expr::Number(-5)));
+        assert_eq!(parse_expression("+5"), Ok(Expr::Number(5)));
+        assert_eq!(parse_expression("--5"), Ok(Expr::Number(5)));
+        assert_eq!(parse_expression("-X"), Ok(Expr::Unary('-', Box::new(Expr::Symbol("X".to_string())))));
+        assert_eq!(eval("~0"), Ok(-1));
+        assert_eq!(eval("!0 + !7"), Ok(1));
+        assert_eq!(eval("-2 * 3"), Ok(-6));
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(eval("0x1F"), Ok(31));
+        assert_eq!(eval("0XfF"), Ok(255));
+        assert_eq!(eval("0b1010"), Ok(10));
+        assert_eq!(eval("0o17"), Ok(15));
+        assert_eq!(eval("1_000_000"), Ok(1_000_000));
+        assert_eq!(eval("0b1111_0000"), Ok(0xF0));
+        assert_eq!(invalid_reason("0b102"), "invalid number 0b102");
+        assert_eq!(invalid_reason("12abc"), "invalid number 12abc");
+    }
+
+    #[test]
+    fn character_literals() {
+        assert_eq!(eval("'A'"), Ok(65));
+        assert_eq!(eval("'\\n'"), Ok(10));
+        assert_eq!(eval("'\\0'"), Ok(0));
+        assert_eq!(eval("'\\''"), Ok(39));
+        assert_eq!(eval("'\\x7f'"), Ok(127));
+        assert_eq!(eval("'\\u{e9}'"), Ok(0xE9));
+        assert_eq!(eval("'a' + 1"), Ok(98));
+        assert_eq!(invalid_reason("''"), "empty character literal");
+        assert_eq!(invalid_reason("'ab'"), "unterminated character literal");
+        assert_eq!(invalid_reason("'\\q'"), "unknown escape \\q");
+        assert_eq!(invalid_reason("'\\x4'"), "\\x must be followed by two hex digits");
+    }
+
+    #[test]
+    fn range_check_bounds() {
+        assert_eq!(Range::BYTE.check(-128), Ok(-128));
+        assert_eq!(Range::BYTE.check(255), Ok(255));
+        assert_eq!(
+            Range::BYTE.check(256),
+            Err(AssemblerErrorKind::ValueOutOfRange { value: 256, min: -128, max: 255 })
+        );
+        assert!(Range::BYTE.check(-129).is_err());
+        assert_eq!(Range::IMMEDIATE.check(u32::MAX as i64), Ok(u32::MAX as i64));
+        assert!(Range::IMMEDIATE.check(u32::MAX as i64 + 1).is_err());
+        assert!(Range::IMMEDIATE.check(i32::MIN as i64 - 1).is_err());
+        assert_eq!(Range::SHIFT.check(31), Ok(31));
+        assert!(Range::SHIFT.check(32).is_err());
+    }
+
+    #[test]
+    fn division_and_shift_errors() {
+        assert_eq!(invalid_reason("1 / 0"), "division by zero");
+        assert_eq!(invalid_reason("1 % (2 - 2)"), "division by zero");
+        assert_eq!(invalid_reason("1 << 64"), "shift amount 64 is out of range");
+        assert_eq!(invalid_reason("1 >> -1"), "shift amount -1 is out of range");
+        assert_eq!(invalid_reason("0x7FFFFFFFFFFFFFFF + 1"), "arithmetic overflow");
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(eval("0 && UNDEFINED"), Ok(0));
+        assert_eq!(eval("1 || UNDEFINED"), Ok(1));
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 && UNDEFINED"), Err(EvalError::Unresolved));
+        assert_eq!(eval("2 && 3"), Ok(1));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(invalid_reason("(1 + 2"), "missing ')'");
+        assert_eq!(invalid_reason("1 +"), "expected a value");
+        assert_eq!(invalid_reason("1 2"), "unexpected number 2");
+        assert_eq!(invalid_reason("1 $ 2"), "unexpected character '$'");
+        assert_eq!(invalid_reason("r1 + 4"), "register r1 cannot be used in an expression");
+    }
+
+    #[test]
+    fn constants_and_cycles() {
+        let symbols = constants(&[("A", "2"), ("B", "A * 3"), ("C", "B + A")]);
+        assert_eq!(parse_expression("C << 1").unwrap().evaluate(&symbols, 0), Ok(16));
+
+        let symbols = constants(&[("X", "Y + 1"), ("Y", "X + 1")]);
+        assert_eq!(
+            parse_expression("X").unwrap().evaluate(&symbols, 0),
+            Err(EvalError::Invalid(AssemblerErrorKind::CircularConstant("X".to_string())))
+        );
+    }
+}
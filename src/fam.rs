@@ -0,0 +1,263 @@
+use crate::load_error::{LoadError, LoadErrorKind};
+
+/// Magic number at the start of every .fam file.
+pub(crate) const FAM_MAGIC: [u8; 4] = *b"\x7fFAM";
+
+/// Container format version written by the assembler and accepted by the loader.
+pub(crate) const FAM_VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = 16;
+const SECTION_ENTRY_SIZE: usize = 12;
+
+const SECTION_DATA: u32 = 1;
+const SECTION_TEXT: u32 = 2;
+
+/// An in-memory .fam program.
+///
+/// On disk the layout is a 16-byte header followed by a section table and the
+/// section contents. All integers are little-endian.
+///
+/// ```text
+///  0  magic          [u8; 4]   "\x7fFAM"
+///  4  version        u16
+///  6  section count  u16
+///  8  entry point    u32       instruction index
+/// 12  checksum       u32       CRC-32 of everything after the header
+/// 16  section table  count * { kind: u32, offset: u32, length: u32 }
+/// ..  section contents
+/// ```
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FamImage {
+    pub(crate) entry: u32,
+    pub(crate) data: Vec<u8>,
+    pub(crate) text: Vec<u8>,
+}
+
+impl FamImage {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let sections = [(SECTION_DATA, &self.data), (SECTION_TEXT, &self.text)];
+
+        let mut body = Vec::new();
+        let mut offset = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+        for (kind, contents) in &sections {
+            body.extend_from_slice(&kind.to_le_bytes());
+            body.extend_from_slice(&(offset as u32).to_le_bytes());
+            body.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            offset += contents.len();
+        }
+        for (_, contents) in &sections {
+            body.extend_from_slice(contents);
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+        bytes.extend_from_slice(&FAM_MAGIC);
+        bytes.extend_from_slice(&FAM_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<FamImage, LoadError> {
+        if bytes.len() < FAM_MAGIC.len() || bytes[..FAM_MAGIC.len()] != FAM_MAGIC {
+            return Err(LoadError::new(
+                LoadErrorKind::BadMagic,
+                "Not a Folia bytecode file (bad magic number)".to_string(),
+            ));
+        }
+
+        if bytes.len() < HEADER_SIZE {
+            return Err(LoadError::new(LoadErrorKind::Truncated, format!(
+                "Truncated .fam file: header needs {} bytes, file has {}",
+                HEADER_SIZE,
+                bytes.len()
+            )));
+        }
+
+        let version = read_u16(bytes, 4);
+        if version != FAM_VERSION {
+            let kind = LoadErrorKind::UnsupportedVersion { found: version, supported: FAM_VERSION };
+            return Err(LoadError::new(kind, format!(
+                "Incompatible .fam version {} (this runtime supports version {})",
+                version, FAM_VERSION
+            )));
+        }
+
+        let section_count = read_u16(bytes, 6) as usize;
+        let entry = read_u32(bytes, 8);
+        let checksum = read_u32(bytes, 12);
+
+        let table_end = HEADER_SIZE + section_count * SECTION_ENTRY_SIZE;
+        if bytes.len() < table_end {
+            return Err(LoadError::new(LoadErrorKind::Truncated, format!(
+                "Truncated .fam file: section table needs {} bytes, file has {}",
+                table_end,
+                bytes.len()
+            )));
+        }
+
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        if actual != checksum {
+            let kind = LoadErrorKind::ChecksumMismatch { expected: checksum, actual };
+            return Err(LoadError::new(kind, format!(
+                "Corrupt .fam file: checksum 0x{:08x} does not match contents (0x{:08x})",
+                checksum, actual
+            )));
+        }
+
+        let mut image = FamImage {
+            entry,
+            ..FamImage::default()
+        };
+        let mut has_text = false;
+
+        for i in 0..section_count {
+            let entry_start = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+            let kind = read_u32(bytes, entry_start);
+            let offset = read_u32(bytes, entry_start + 4) as usize;
+            let length = read_u32(bytes, entry_start + 8) as usize;
+
+            let end = offset.checked_add(length).filter(|&end| end <= bytes.len());
+            let Some(end) = end else {
+                return Err(LoadError::new(LoadErrorKind::Truncated, format!(
+                    "Truncated .fam file: section {} ends past the end of the file ({} bytes)",
+                    i,
+                    bytes.len()
+                )));
+            };
+
+            match kind {
+                SECTION_DATA => image.data = bytes[offset..end].to_vec(),
+                SECTION_TEXT => {
+                    image.text = bytes[offset..end].to_vec();
+                    has_text = true;
+                }
+                _ => {}
+            }
+        }
+
+        if !has_text {
+            return Err(LoadError::new(
+                LoadErrorKind::MissingTextSection,
+                "Invalid .fam file: missing text section".to_string(),
+            ));
+        }
+
+        Ok(image)
+    }
+}
+
+#[inline]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[inline]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// CRC-32 (IEEE 802.3), computed bitwise to avoid a lookup table.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> FamImage {
+        FamImage {
+            entry: 1,
+            data: b"hello".to_vec(),
+            text: vec![0x11; 16],
+        }
+    }
+
+    fn load_error(bytes: &[u8]) -> LoadErrorKind {
+        FamImage::from_bytes(bytes).expect_err("image should be rejected").kind
+    }
+
+    /// Recomputes the checksum after a test edits the body, so the edit is
+    /// what gets rejected rather than the checksum.
+    fn reseal(bytes: &mut [u8]) {
+        let checksum = crc32(&bytes[HEADER_SIZE..]);
+        bytes[12..16].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let loaded = FamImage::from_bytes(&image().to_bytes()).unwrap();
+        assert_eq!(loaded.entry, 1);
+        assert_eq!(loaded.data, b"hello");
+        assert_eq!(loaded.text, vec![0x11; 16]);
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = image().to_bytes();
+        bytes[1] = b'X';
+        assert_eq!(load_error(&bytes), LoadErrorKind::BadMagic);
+        assert_eq!(load_error(b"\x7fFA"), LoadErrorKind::BadMagic);
+        assert_eq!(load_error(b""), LoadErrorKind::BadMagic);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = image().to_bytes();
+        assert_eq!(load_error(&bytes[..HEADER_SIZE - 1]), LoadErrorKind::Truncated);
+        assert_eq!(load_error(&bytes[..HEADER_SIZE + SECTION_ENTRY_SIZE]), LoadErrorKind::Truncated);
+
+        let mut cut = bytes[..bytes.len() - 1].to_vec();
+        reseal(&mut cut);
+        assert_eq!(load_error(&cut), LoadErrorKind::Truncated);
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut bytes = image().to_bytes();
+        bytes[4..6].copy_from_slice(&(FAM_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            load_error(&bytes),
+            LoadErrorKind::UnsupportedVersion { found: FAM_VERSION + 1, supported: FAM_VERSION }
+        );
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let mut bytes = image().to_bytes();
+        let expected = read_u32(&bytes, 12);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let actual = crc32(&bytes[HEADER_SIZE..]);
+        assert_eq!(load_error(&bytes), LoadErrorKind::ChecksumMismatch { expected, actual });
+    }
+
+    #[test]
+    fn rejects_missing_text_section() {
+        let mut bytes = image().to_bytes();
+        let text_entry = HEADER_SIZE + SECTION_ENTRY_SIZE;
+        bytes[text_entry..text_entry + 4].copy_from_slice(&99u32.to_le_bytes());
+        reseal(&mut bytes);
+        assert_eq!(load_error(&bytes), LoadErrorKind::MissingTextSection);
+    }
+}
@@ -0,0 +1,48 @@
+//! Folia: an assembler and register-based virtual machine.
+//!
+//! The same API backs the `folia_am` command-line tool. A program is
+//! assembled into `.fam` bytecode, loaded into a [`Runtime`] and executed,
+//! either to completion or in bounded slices:
+//!
+//! ```
+//! use folia_am::{Assembler, Runtime};
+//!
+//! let bytecode = Assembler::new()
+//!     .assemble_str("answer.s", "MOV r1, #40\nADD r1, r1, #2\nPRINT r1\nHALT")
+//!     .expect("program assembles");
+//!
+//! let mut vm = Runtime::with_io(std::io::empty(), Vec::new());
+//! vm.load_program(&bytecode).expect("bytecode loads");
+//! let still_running = vm.run_for(100).expect("program runs");
+//!
+//! assert!(!still_running);
+//! assert_eq!(vm.register(1), Some(42));
+//! assert_eq!(vm.into_output(), b"42");
+//! ```
+
+// `AssemblerError` carries the rendered source excerpt and is only built on
+// the error path, so returning it by value is fine.
+#![allow(clippy::result_large_err)]
+
+mod fam;
+mod linker;
+mod load_error;
+mod op_codes;
+mod preprocessor;
+mod runtime_error;
+mod assembler;
+mod assembler_error;
+mod disassembler;
+mod expression;
+mod runtime;
+
+pub use crate::assembler::{Assembler, SourceFile};
+pub use crate::assembler_error::{AssemblerError, AssemblerErrorKind};
+pub use crate::disassembler::disassemble;
+pub use crate::load_error::{LoadError, LoadErrorKind};
+pub use crate::op_codes::OpCode;
+pub use crate::runtime::{
+    ExecutionLimits, Instruction, OperandMode, Runtime, StackFrame, DATA_BASE, DEFAULT_MEMORY_SIZE, FRAME_POINTER,
+    MAX_MEMORY_SIZE, REGISTER_COUNT, STACK_POINTER,
+};
+pub use crate::runtime_error::{RuntimeError, RuntimeErrorKind};
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::assembler::SourceLocation;
+use crate::assembler_error::{AssemblerError, AssemblerErrorKind};
+use crate::expression::{EvalError, Expr, Range, Symbols, CONSTANT_DEPTH_LIMIT};
+use crate::fam::FamImage;
+use crate::runtime::{Instruction, DATA_BASE};
+
+/// A label's position relative to the start of its own object's section, or a
+/// constant defined with `.equ`.
+#[derive(Debug, Clone)]
+pub(crate) enum Symbol {
+    Text(usize),
+    Data(usize),
+    Constant(Rc<Expr>),
+}
+
+/// Where the value of a relocation is written.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Field {
+    /// The immediate of the instruction at this index.
+    Immediate(usize),
+    /// `size` little-endian bytes at this offset in the data section.
+    Data { offset: usize, size: usize },
+}
+
+/// An expression that refers to labels, evaluated once section bases are known.
+pub(crate) struct Relocation {
+    pub(crate) field: Field,
+    pub(crate) expr: Expr,
+    pub(crate) range: Range,
+    pub(crate) location: SourceLocation,
+}
+
+/// The relocatable output of assembling a single source file.
+pub(crate) struct ObjectFile {
+    pub(crate) instructions: Vec<Instruction>,
+    pub(crate) data: Vec<u8>,
+    /// The alignment the object's data must start at.
+    pub(crate) data_alignment: usize,
+    pub(crate) symbols: HashMap<String, Symbol>,
+    pub(crate) globals: Vec<(String, SourceLocation)>,
+    pub(crate) externs: HashMap<String, SourceLocation>,
+    pub(crate) relocations: Vec<Relocation>,
+    pub(crate) start: Option<(String, SourceLocation)>,
+}
+
+/// Lays out object files one after another and resolves symbols between them.
+///
+/// Labels are private to the file that defines them unless exported with
+/// `.global`. A file may only use another file's global after importing it
+/// with `.extern`.
+pub(crate) struct Linker {
+    text_bases: Vec<usize>,
+    data_bases: Vec<usize>,
+    globals: HashMap<String, (usize, Symbol, SourceLocation)>,
+    errors: Vec<AssemblerError>,
+}
+
+/// Symbol lookup from the point of view of one object file.
+struct ObjectSymbols<'a> {
+    linker: &'a Linker,
+    objects: &'a [ObjectFile],
+    object: usize,
+}
+
+impl Symbols for ObjectSymbols<'_> {
+    fn value(&self, name: &str, depth: usize) -> Result<i64, EvalError> {
+        let (owner, symbol) = self.linker.resolve(&self.objects[self.object], name)?;
+        let owner = owner.unwrap_or(self.object);
+        match symbol {
+            Symbol::Text(index) => Ok((self.linker.text_bases[owner] + index) as i64),
+            Symbol::Data(offset) => Ok((DATA_BASE + self.linker.data_bases[owner] + offset) as i64),
+            Symbol::Constant(expr) => {
+                if depth >= CONSTANT_DEPTH_LIMIT {
+                    return Err(AssemblerErrorKind::CircularConstant(name.to_string()).into());
+                }
+                let symbols = ObjectSymbols {
+                    object: owner,
+                    ..*self
+                };
+                expr.evaluate(&symbols, depth + 1)
+            }
+        }
+    }
+}
+
+impl Linker {
+    pub(crate) fn new() -> Self {
+        Linker {
+            text_bases: Vec::new(),
+            data_bases: Vec::new(),
+            globals: HashMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub(crate) fn link(mut self, mut objects: Vec<ObjectFile>) -> Result<FamImage, Vec<AssemblerError>> {
+        let mut text_len = 0;
+        let mut data_len: usize = 0;
+        for object in &objects {
+            self.text_bases.push(text_len);
+            data_len = data_len.next_multiple_of(object.data_alignment);
+            self.data_bases.push(data_len);
+            text_len += object.instructions.len();
+            data_len += object.data.len();
+        }
+
+        self.collect_globals(&objects);
+
+        let mut patches = Vec::new();
+        let mut errors = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            let symbols = ObjectSymbols {
+                linker: &self,
+                objects: &objects,
+                object: index,
+            };
+            for relocation in &object.relocations {
+                let value = match relocation.expr.evaluate(&symbols, 0) {
+                    Ok(value) => relocation.range.check(value),
+                    Err(EvalError::Invalid(kind)) => Err(kind),
+                    Err(EvalError::Unresolved) => unreachable!("the linker resolves every symbol"),
+                };
+                match value {
+                    Ok(value) => patches.push((index, relocation.field, value)),
+                    Err(kind) => errors.push(relocation.location.error(kind)),
+                }
+            }
+        }
+
+        for (index, field, value) in patches {
+            let object = &mut objects[index];
+            match field {
+                Field::Immediate(instruction) => object.instructions[instruction].immediate = value as i32,
+                Field::Data { offset, size } => {
+                    object.data[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+                }
+            }
+        }
+
+        let mut start = None;
+        for (index, object) in objects.iter().enumerate() {
+            let Some((name, location)) = &object.start else {
+                continue;
+            };
+            if start.is_some() {
+                errors.push(location.error(AssemblerErrorKind::DuplicateStart));
+                continue;
+            }
+            let symbols = ObjectSymbols {
+                linker: &self,
+                objects: &objects,
+                object: index,
+            };
+            match symbols.value(name, 0) {
+                Ok(pc) => start = Some(pc),
+                Err(EvalError::Invalid(kind)) => errors.push(location.error(kind)),
+                Err(EvalError::Unresolved) => unreachable!("the linker resolves every symbol"),
+            }
+        }
+
+        self.errors.extend(errors);
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let mut text = Vec::with_capacity(text_len * 8);
+        let mut data = Vec::with_capacity(data_len);
+        for (object, &base) in objects.iter().zip(&self.data_bases) {
+            for instruction in &object.instructions {
+                text.extend_from_slice(&instruction.encode());
+            }
+            data.resize(base, 0);
+            data.extend_from_slice(&object.data);
+        }
+
+        Ok(FamImage {
+            entry: start.unwrap_or(0) as u32,
+            data,
+            text,
+        })
+    }
+
+    fn collect_globals(&mut self, objects: &[ObjectFile]) {
+        for (index, object) in objects.iter().enumerate() {
+            for (name, location) in &object.globals {
+                let Some(symbol) = object.symbols.get(name) else {
+                    self.errors.push(location.error(AssemblerErrorKind::UndefinedGlobal(name.clone())));
+                    continue;
+                };
+
+                if let Some((_, _, previous)) = self.globals.get(name) {
+                    let previous = format!("{}:{}", previous.path, previous.line);
+                    self.errors.push(location.error(AssemblerErrorKind::DuplicateSymbol {
+                        name: name.clone(),
+                        previous,
+                    }));
+                    continue;
+                }
+
+                self.globals.insert(name.clone(), (index, symbol.clone(), location.clone()));
+            }
+        }
+    }
+
+    /// Finds the symbol `name` refers to in `object`: its own symbol first, then
+    /// an imported global. The owner is `None` for the object's own symbols.
+    fn resolve<'a>(
+        &'a self,
+        object: &'a ObjectFile,
+        name: &str,
+    ) -> Result<(Option<usize>, &'a Symbol), AssemblerErrorKind> {
+        if let Some(symbol) = object.symbols.get(name) {
+            return Ok((None, symbol));
+        }
+
+        match self.globals.get(name) {
+            Some((owner, symbol, _)) if object.externs.contains_key(name) => Ok((Some(*owner), symbol)),
+            Some(_) => Err(AssemblerErrorKind::MissingExtern(name.to_string())),
+            None => Err(AssemblerErrorKind::UndefinedLabel(name.to_string())),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a program could not be loaded. `LoadError::message` carries the
+/// details, such as the sizes or offsets involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadErrorKind {
+    /// The bytes do not start with the `.fam` magic number.
+    BadMagic,
+    /// The header, section table, a section or the text section is cut short.
+    Truncated,
+    UnsupportedVersion { found: u16, supported: u16 },
+    ChecksumMismatch { expected: u32, actual: u32 },
+    MissingTextSection,
+    /// The data section does not fit below the stack.
+    DataTooLarge,
+    /// The entry point is not an instruction of the program.
+    InvalidStart,
+    /// An instruction names a register that does not exist.
+    InvalidRegister { instruction: usize, register: u8 },
+    /// A jump or call targets an address outside the program.
+    InvalidJumpTarget { instruction: usize, target: i32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new(kind: LoadErrorKind, message: String) -> Self {
+        LoadError { kind, message }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for LoadError {}
@@ -1,32 +1,161 @@
-mod op_codes;
-mod runtime_error;
-mod assembler;
-mod runtime;
+mod test_runner;
 
 use std::env;
 use std::fs;
 use std::process;
 use std::path::Path;
-use crate::assembler::Assembler;
-use crate::runtime::Runtime;
+use std::time::Duration;
+use folia_am::{disassemble, Assembler, AssemblerError, ExecutionLimits, Runtime, SourceFile, MAX_MEMORY_SIZE};
 
-fn read_and_concatenate_files(files: &[String]) -> Result<String, String> {
-    let mut combined_source = String::new();
+struct RunOptions {
+    files: Vec<String>,
+    memory_size: Option<usize>,
+    stack_size: Option<usize>,
+    limits: ExecutionLimits,
+    halt_required: bool,
+    defines: Vec<(String, i64)>,
+}
+
+impl RunOptions {
+    /// Whether any option that only affects running a program was given.
+    fn has_runtime_options(&self) -> bool {
+        self.memory_size.is_some()
+            || self.stack_size.is_some()
+            || self.limits.max_steps.is_some()
+            || self.limits.timeout.is_some()
+            || self.halt_required
+    }
+}
 
-    for (i, file) in files.iter().enumerate() {
-        let content = fs::read_to_string(file)
-            .map_err(|e| format!("Error reading file {}: {}", file, e))?;
+fn parse_size(value: &str) -> Result<usize, String> {
+    let lower = value.to_lowercase();
+    let (digits, multiplier) = if let Some(kb) = lower.strip_suffix('k') {
+        (kb, 1024)
+    } else if let Some(mb) = lower.strip_suffix('m') {
+        (mb, 1024 * 1024)
+    } else {
+        (lower.as_str(), 1)
+    };
 
-        if i > 0 {
-            combined_source.push('\n');
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+/// Parses a duration in seconds, or in milliseconds with an `ms` suffix
+/// (`30`, `30s`, `500ms`).
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let lower = value.to_lowercase();
+    let parsed = if let Some(ms) = lower.strip_suffix("ms") {
+        ms.parse::<u64>().ok().map(Duration::from_millis)
+    } else {
+        lower.strip_suffix('s').unwrap_or(&lower).parse::<u64>().ok().map(Duration::from_secs)
+    };
+    parsed.ok_or_else(|| format!("Invalid duration: {}", value))
+}
+
+/// Parses `NAME=value` (or just `NAME`, meaning 1) from a `-D` option.
+fn parse_define(definition: &str) -> Result<(String, i64), String> {
+    let (name, value) = definition.split_once('=').unwrap_or((definition, "1"));
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid_name {
+        return Err(format!("Invalid name in -D {}", definition));
+    }
+
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let parsed = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse::<i64>(),
+    };
+    let value = parsed.map_err(|_| format!("Invalid value in -D {}", definition))?;
+    Ok((name.to_string(), if negative { -value } else { value }))
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        files: Vec::new(),
+        memory_size: None,
+        stack_size: None,
+        limits: ExecutionLimits::new(),
+        halt_required: false,
+        defines: Vec::new(),
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--memory-size" => {
+                let value = iter.next().ok_or("--memory-size requires a value")?;
+                let size = parse_size(value)?;
+                if size > MAX_MEMORY_SIZE {
+                    return Err(format!("Memory size {} is larger than the maximum of {} bytes", value, MAX_MEMORY_SIZE));
+                }
+                options.memory_size = Some(size);
+            }
+            "--stack-size" => {
+                let value = iter.next().ok_or("--stack-size requires a value")?;
+                options.stack_size = Some(parse_size(value)?);
+            }
+            "--max-steps" => {
+                let value = iter.next().ok_or("--max-steps requires a value")?;
+                let steps = value.parse().map_err(|_| format!("Invalid step count: {}", value))?;
+                options.limits = options.limits.with_max_steps(steps);
+            }
+            "--timeout" => {
+                let value = iter.next().ok_or("--timeout requires a value")?;
+                options.limits = options.limits.with_timeout(parse_duration(value)?);
+            }
+            "--require-halt" => options.halt_required = true,
+            "-D" => {
+                let definition = iter.next().ok_or("-D requires NAME=value")?;
+                options.defines.push(parse_define(definition)?);
+            }
+            flag if flag.starts_with("-D") => options.defines.push(parse_define(&flag[2..])?),
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown option: {}", flag));
+            }
+            _ => options.files.push(arg.clone()),
         }
-        combined_source.push_str(&content);
     }
 
-    Ok(combined_source)
+    Ok(options)
+}
+
+fn read_source_files(files: &[String]) -> Result<Vec<SourceFile>, String> {
+    files
+        .iter()
+        .map(|file| {
+            let text = fs::read_to_string(file)
+                .map_err(|e| format!("Error reading file {}: {}", file, e))?;
+            Ok(SourceFile::new(file.clone(), text))
+        })
+        .collect()
+}
+
+fn format_assembler_errors(errors: &[AssemblerError]) -> String {
+    let mut message = String::new();
+    for error in errors {
+        message.push_str(&format!("{}\n\n", error));
+    }
+    message.push_str(&format!("Assembly failed with {} error(s)", errors.len()));
+    message
 }
 
-fn load_bytecode(input_files: &[String]) -> Result<Vec<u8>, String> {
+fn new_assembler(defines: &[(String, i64)]) -> Assembler {
+    let mut assembler = Assembler::new();
+    for (name, value) in defines {
+        assembler.define(name.clone(), *value);
+    }
+    assembler
+}
+
+fn load_bytecode(input_files: &[String], defines: &[(String, i64)]) -> Result<Vec<u8>, String> {
     let first_file = &input_files[0];
     let path = Path::new(first_file);
     let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
@@ -40,9 +169,8 @@ fn load_bytecode(input_files: &[String]) -> Result<Vec<u8>, String> {
             }
         }
 
-        let source = read_and_concatenate_files(input_files)?;
-        let mut assembler = Assembler::new();
-        assembler.assemble(&source).map_err(|e| format!("Assembler error: {}", e))
+        let sources = read_source_files(input_files)?;
+        new_assembler(defines).assemble(&sources).map_err(|errors| format_assembler_errors(&errors))
     } else {
         if input_files.len() > 1 {
             return Err("Multiple files are not supported for bytecode (.fam) files".to_string());
@@ -66,6 +194,16 @@ fn main() {
         println!("  step <source.asm> [source2.asm] ...   - Interactive step debugger");
         println!("  trace <program.fam>                    - Run with execution trace");
         println!("  trace <source.asm> [source2.asm] ...  - Run with execution trace");
+        println!("  disasm <program.fam>                   - Print a bytecode program as assembly");
+        println!("  test <file|dir> ...                    - Run programs and compare with their .out/.err files");
+        println!("Options for compile, run, debug, step and trace:");
+        println!("  -D NAME[=value]                        - Predefine a constant for .if/.ifdef (default value 1)");
+        println!("Options for run, debug, step and trace:");
+        println!("  --memory-size <bytes>                  - Data memory size, e.g. 65536 or 64k (default 4k)");
+        println!("  --stack-size <bytes>                   - Stack size at the end of data memory (default: a quarter of it)");
+        println!("  --max-steps <count>                    - Stop with an error after this many instructions");
+        println!("  --timeout <duration>                   - Stop with an error after this long, e.g. 10s or 500ms");
+        println!("  --require-halt                         - Treat running past the last instruction as an error");
         process::exit(1);
     }
 
@@ -73,13 +211,25 @@ fn main() {
 
     match command.as_str() {
         "compile" => {
-            if args.len() < 3 {
-                println!("Usage: {} compile <source.asm> [source2.asm] ...", args[0]);
+            let options = match parse_run_options(&args[2..]) {
+                Ok(options) if !options.has_runtime_options() => options,
+                Ok(_) => {
+                    println!("--memory-size, --stack-size, --max-steps, --timeout and --require-halt only apply when running a program");
+                    process::exit(1);
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            if options.files.is_empty() {
+                println!("Usage: {} compile [-D NAME=value] <source.asm> [source2.asm] ...", args[0]);
                 process::exit(1);
             }
 
-            let source_files: Vec<String> = args[2..].to_vec();
-            let source = match read_and_concatenate_files(&source_files) {
+            let source_files = options.files;
+            let sources = match read_source_files(&source_files) {
                 Ok(content) => content,
                 Err(e) => {
                     println!("{}", e);
@@ -87,8 +237,7 @@ fn main() {
                 }
             };
 
-            let mut assembler = Assembler::new();
-            match assembler.assemble(&source) {
+            match new_assembler(&options.defines).assemble(&sources) {
                 Ok(bytecode) => {
                     let output_file = if source_files.len() == 1 {
                         source_files[0].replace(".asm", ".fam").replace(".s", ".fam")
@@ -103,20 +252,27 @@ fn main() {
                         }
                     }
                 }
-                Err(e) => {
-                    println!("Assembly error: {}", e);
+                Err(errors) => {
+                    println!("{}", format_assembler_errors(&errors));
                     process::exit(1);
                 }
             }
         }
         "run" | "debug" | "step" | "trace" => {
-            if args.len() < 3 {
-                println!("Usage: {} {} <program.fam|source.asm> [source2.asm] ...", args[0], command);
+            let options = match parse_run_options(&args[2..]) {
+                Ok(options) => options,
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            if options.files.is_empty() {
+                println!("Usage: {} {} [--memory-size <bytes>] [--stack-size <bytes>] [--max-steps <count>] [--timeout <duration>] [--require-halt] [-D NAME=value] <program.fam|source.asm> [source2.asm] ...", args[0], command);
                 process::exit(1);
             }
 
-            let input_files: Vec<String> = args[2..].to_vec();
-            let bytecode = match load_bytecode(&input_files) {
+            let bytecode = match load_bytecode(&options.files, &options.defines) {
                 Ok(data) => data,
                 Err(e) => {
                     println!("{}", e);
@@ -124,12 +280,21 @@ fn main() {
                 }
             };
 
-            let mut vm = Runtime::new();
-            vm.load_program(&bytecode);
+            let mut vm = Runtime::new().with_halt_required(options.halt_required);
+            if let Some(memory_size) = options.memory_size {
+                vm = vm.with_memory_size(memory_size);
+            }
+            if let Some(stack_size) = options.stack_size {
+                vm = vm.with_stack_size(stack_size);
+            }
+            if let Err(e) = vm.load_program(&bytecode) {
+                println!("Load error: {}", e);
+                process::exit(1);
+            }
 
             match command.as_str() {
                 "run" => {
-                    match vm.run() {
+                    match vm.run_with_limits(options.limits) {
                         Ok(()) => {}
                         Err(error) => {
                             error.print_error();
@@ -142,7 +307,7 @@ fn main() {
                     vm.debug_state();
                     println!("\nRunning program...\n");
 
-                    match vm.run() {
+                    match vm.run_with_limits(options.limits) {
                         Ok(()) => {
                             println!("\nFinal state:");
                             vm.debug_state();
@@ -159,33 +324,25 @@ fn main() {
                     println!("Interactive Step Debugger Started");
                     vm.debug_state();
 
-                    loop {
-                        match vm.debug_step() {
-                            Ok(true) => {
-                                match vm.run() {
-                                    Ok(()) => break,
-                                    Err(error) => {
-                                        error.print_error();
-                                        vm.debug_state();
-                                        break;
-                                    }
-                                }
-                            }
-                            Ok(false) => {
-                                println!("Program terminated");
-                                break;
-                            }
-                            Err(error) => {
+                    match vm.debug_step() {
+                        Ok(true) => {
+                            if let Err(error) = vm.run_with_limits(options.limits) {
                                 error.print_error();
                                 vm.debug_state();
-                                break;
                             }
                         }
+                        Ok(false) => {
+                            println!("Program terminated");
+                        }
+                        Err(error) => {
+                            error.print_error();
+                            vm.debug_state();
+                        }
                     }
                 }
                 "trace" => {
                     println!("📊 Execution Trace Mode");
-                    match vm.run_with_trace() {
+                    match vm.run_with_trace(options.limits) {
                         Ok(()) => {
                             println!("\nProgram completed");
                             vm.debug_performance();
@@ -200,9 +357,46 @@ fn main() {
                 _ => unreachable!()
             }
         }
+        "disasm" => {
+            if args.len() < 3 {
+                println!("Usage: {} disasm <program.fam|source.asm> [source2.asm] ...", args[0]);
+                process::exit(1);
+            }
+
+            let bytecode = match load_bytecode(&args[2..], &[]) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+            match disassemble(&bytecode) {
+                Ok(source) => print!("{}", source),
+                Err(e) => {
+                    println!("Disassembly error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        "test" => {
+            if args.len() < 3 {
+                println!("Usage: {} test <source.asm|directory> ...", args[0]);
+                process::exit(1);
+            }
+
+            match test_runner::run_tests(&args[2..]) {
+                Ok(true) => {}
+                Ok(false) => process::exit(1),
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
         _ => {
             println!("Unknown command: {}", command);
-            println!("Available commands: compile, run, debug, step, trace");
+            println!("Available commands: compile, run, debug, step, trace, disasm, test");
             process::exit(1);
         }
     }
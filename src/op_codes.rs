@@ -1,3 +1,4 @@
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OpCode {
     MOV = 0x01,
@@ -28,11 +29,21 @@ pub enum OpCode {
     PRINT = 0x1A,
     PRINTC = 0x1B,
     INPUT = 0x1C,
+    LOAD = 0x1D,
+    LOADB = 0x1E,
+    JLE = 0x1F,
+    JGE = 0x20,
+    JCS = 0x21,
+    JCC = 0x22,
+    JVS = 0x23,
+    JVC = 0x24,
+    JA = 0x25,
+    JB = 0x26,
 }
 
 impl OpCode {
     #[inline]
-    pub(crate) fn from_u8(value: u8) -> Option<OpCode> {
+    pub fn from_u8(value: u8) -> Option<OpCode> {
         match value {
             0x01 => Some(OpCode::MOV),
             0x02 => Some(OpCode::ADD),
@@ -62,7 +73,39 @@ impl OpCode {
             0x1A => Some(OpCode::PRINT),
             0x1B => Some(OpCode::PRINTC),
             0x1C => Some(OpCode::INPUT),
+            0x1D => Some(OpCode::LOAD),
+            0x1E => Some(OpCode::LOADB),
+            0x1F => Some(OpCode::JLE),
+            0x20 => Some(OpCode::JGE),
+            0x21 => Some(OpCode::JCS),
+            0x22 => Some(OpCode::JCC),
+            0x23 => Some(OpCode::JVS),
+            0x24 => Some(OpCode::JVC),
+            0x25 => Some(OpCode::JA),
+            0x26 => Some(OpCode::JB),
             _ => None,
         }
     }
+
+    /// Jumps and calls, whose immediate is an instruction index.
+    #[inline]
+    pub fn is_branch(self) -> bool {
+        matches!(
+            self,
+            OpCode::JMP
+                | OpCode::JEQ
+                | OpCode::JNE
+                | OpCode::JLT
+                | OpCode::JGT
+                | OpCode::JLE
+                | OpCode::JGE
+                | OpCode::JCS
+                | OpCode::JCC
+                | OpCode::JVS
+                | OpCode::JVC
+                | OpCode::JA
+                | OpCode::JB
+                | OpCode::CALL
+        )
+    }
 }
\ No newline at end of file
@@ -0,0 +1,646 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::assembler::{Line, SourceFile, SourceLocation};
+use crate::assembler_error::{AssemblerError, AssemblerErrorKind};
+use crate::expression::{parse_expression, EvalError, Expr, Symbols, CONSTANT_DEPTH_LIMIT};
+
+/// How deeply macro invocations may nest before expansion is abandoned.
+const MACRO_DEPTH_LIMIT: usize = 64;
+
+/// One macro invocation in the chain that produced a line.
+#[derive(Debug, Clone)]
+pub(crate) struct Expansion {
+    name: String,
+    path: Rc<str>,
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for Expansion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "in expansion of macro {} invoked at {}:{}:{}",
+            self.name, self.path, self.line, self.column
+        )
+    }
+}
+
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Line>,
+    location: SourceLocation,
+}
+
+/// An open `.if`/`.ifdef`/`.ifndef` block.
+struct Conditional {
+    /// Whether lines in the current branch are assembled.
+    active: bool,
+    /// Whether the enclosing block is active; an inactive block never
+    /// becomes active through `.else`.
+    parent_active: bool,
+    seen_else: bool,
+    location: SourceLocation,
+}
+
+/// Constants the preprocessor knows about: `-D` definitions and `.equ`/`.set`
+/// lines it has already passed. Labels have no value until link time.
+struct Constants<'a>(&'a HashMap<String, Rc<Expr>>);
+
+impl Symbols for Constants<'_> {
+    fn value(&self, name: &str, depth: usize) -> Result<i64, EvalError> {
+        let Some(expr) = self.0.get(name) else {
+            return Err(EvalError::Unresolved);
+        };
+        if depth >= CONSTANT_DEPTH_LIMIT {
+            return Err(AssemblerErrorKind::CircularConstant(name.to_string()).into());
+        }
+        expr.evaluate(self, depth + 1)
+    }
+}
+
+/// Handles macros, `.include` and conditional assembly before lines reach
+/// the assembler.
+///
+/// ```text
+/// .macro print msg
+///     MOV r1, \msg
+///     CALL print_string
+/// .endm
+/// ```
+///
+/// `\name` is replaced with the argument given for parameter `name` and `\@`
+/// with a number unique to each expansion. Labels defined in a macro body are
+/// renamed per expansion, so a macro containing a loop can be used twice.
+///
+/// `.include "path"` splices in another file, resolved relative to the
+/// including one. `.if expr`, `.ifdef NAME` and `.ifndef NAME` blocks, with an
+/// optional `.else` and a closing `.endif`, drop the lines of the branch not
+/// taken; conditions may only use constants defined before them.
+pub(crate) struct Preprocessor {
+    macros: HashMap<String, Rc<Macro>>,
+    constants: HashMap<String, Rc<Expr>>,
+    /// Files currently being read, outermost first, to detect include cycles.
+    include_stack: Vec<(PathBuf, Rc<str>)>,
+    expansions: usize,
+    errors: Vec<AssemblerError>,
+}
+
+impl Preprocessor {
+    pub(crate) fn new(defines: &[(String, i64)]) -> Self {
+        Preprocessor {
+            macros: HashMap::new(),
+            constants: defines
+                .iter()
+                .map(|(name, value)| (name.clone(), Rc::new(Expr::Number(*value))))
+                .collect(),
+            include_stack: Vec::new(),
+            expansions: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    pub(crate) fn process(&mut self, file: &SourceFile, errors: &mut Vec<AssemblerError>) -> Vec<Line> {
+        let path: Rc<str> = Rc::from(file.path.as_str());
+        let lines = self.read_lines(&path, &file.text, Rc::from(Vec::new()));
+
+        let mut out = Vec::new();
+        self.include_stack.push((canonical(Path::new(&file.path)), path));
+        self.expand(lines, 0, &mut out);
+        self.include_stack.pop();
+        errors.append(&mut self.errors);
+        out
+    }
+
+    fn expand(&mut self, lines: Vec<Line>, depth: usize, out: &mut Vec<Line>) {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let Some((offset, statement)) = find_statement(&line.code) else {
+                out.push(line);
+                continue;
+            };
+            let word_len = statement.find(char::is_whitespace).unwrap_or(statement.len());
+            let word = &statement[..word_len];
+            let argument = statement[word_len..].trim();
+
+            if matches!(word, ".if" | ".ifdef" | ".ifndef" | ".else" | ".endif") {
+                if let Err(error) = self.conditional(&mut conditionals, &line, offset, word, argument) {
+                    self.errors.push(error);
+                }
+                continue;
+            }
+            if conditionals.last().is_some_and(|block| !block.active) {
+                continue;
+            }
+
+            match word {
+                ".include" => {
+                    if let Err(error) = self.include(&line, offset, argument, depth, out) {
+                        self.errors.push(error);
+                    }
+                }
+                ".equ" | ".set" => {
+                    if let Some((name, value)) = argument.split_once(',')
+                        && let Ok(expr) = parse_expression(value)
+                    {
+                        self.constants.insert(name.trim().to_string(), Rc::new(expr));
+                    }
+                    out.push(line);
+                }
+                ".macro" => {
+                    if let Err(error) = self.define(&line, offset, statement, &mut lines) {
+                        self.errors.push(error);
+                    }
+                }
+                ".endm" => self.errors.push(line.error(AssemblerErrorKind::UnexpectedEndm, offset, word_len)),
+                _ => match self.macros.get(word).cloned() {
+                    Some(definition) => {
+                        if !line.code[..offset].trim().is_empty() {
+                            out.push(Line {
+                                code: Rc::from(&line.code[..offset]),
+                                ..line.clone()
+                            });
+                        }
+                        let args = &statement[word_len..];
+                        if let Err(error) = self.invoke(&definition, &line, offset, args, depth, out) {
+                            self.errors.push(error);
+                        }
+                    }
+                    None => out.push(line),
+                },
+            }
+        }
+
+        for block in conditionals {
+            self.errors.push(block.location.error(AssemblerErrorKind::UnterminatedConditional));
+        }
+    }
+
+    /// Splits a file into lines with their comments blanked out.
+    fn read_lines(&mut self, path: &Rc<str>, text: &str, expansion: Rc<[Expansion]>) -> Vec<Line> {
+        let mut open_comment = None;
+        let lines: Vec<Line> = text
+            .lines()
+            .enumerate()
+            .map(|(index, text)| Line {
+                path: path.clone(),
+                number: index + 1,
+                code: Rc::from(strip_comments(text, index, &mut open_comment)),
+                text: Rc::from(text),
+                expansion: expansion.clone(),
+            })
+            .collect();
+
+        if let Some((index, offset)) = open_comment {
+            self.errors.push(lines[index].error(AssemblerErrorKind::UnterminatedComment, offset, 2));
+        }
+        lines
+    }
+
+    /// Opens, flips or closes a conditional block.
+    fn conditional(
+        &mut self,
+        conditionals: &mut Vec<Conditional>,
+        line: &Line,
+        offset: usize,
+        word: &str,
+        argument: &str,
+    ) -> Result<(), AssemblerError> {
+        let parent_active = conditionals.last().is_none_or(|block| block.active);
+        let invalid = |reason: String| line.error(AssemblerErrorKind::InvalidConditional(reason), offset, word.len());
+
+        match word {
+            ".else" => {
+                let Some(block) = conditionals.last_mut() else {
+                    return Err(invalid(".else without a matching .if".to_string()));
+                };
+                if block.seen_else {
+                    return Err(invalid("a block can only have one .else".to_string()));
+                }
+                block.seen_else = true;
+                block.active = block.parent_active && !block.active;
+            }
+            ".endif" => {
+                if conditionals.pop().is_none() {
+                    return Err(invalid(".endif without a matching .if".to_string()));
+                }
+            }
+            _ => {
+                // Push the block before evaluating, so a bad condition does not
+                // also unbalance the matching .endif.
+                conditionals.push(Conditional {
+                    active: false,
+                    parent_active,
+                    seen_else: false,
+                    location: line.location(offset, word.len()),
+                });
+                if !parent_active {
+                    return Ok(());
+                }
+                let active = match word {
+                    ".if" => self.condition(line, offset + word.len(), argument)?,
+                    _ if !is_identifier(argument) => {
+                        return Err(invalid(format!("{} expects a symbol name", word)));
+                    }
+                    ".ifdef" => self.constants.contains_key(argument),
+                    _ => !self.constants.contains_key(argument),
+                };
+                if let Some(block) = conditionals.last_mut() {
+                    block.active = active;
+                }
+            }
+        }
+
+        if !argument.is_empty() && matches!(word, ".else" | ".endif") {
+            let argument_offset = offset + line.code[offset..].find(argument).unwrap_or(0);
+            return Err(line.error(
+                AssemblerErrorKind::TooManyOperands {
+                    name: word.to_string(),
+                    expected: 0,
+                    found: 1,
+                },
+                argument_offset,
+                argument.len(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Evaluates the expression of an `.if`; nonzero is true.
+    fn condition(&self, line: &Line, offset: usize, argument: &str) -> Result<bool, AssemblerError> {
+        let offset = offset + line.code[offset..].find(argument).unwrap_or(0);
+        let error = |kind| line.error(kind, offset, argument.len());
+        let expr = parse_expression(argument).map_err(error)?;
+        match expr.evaluate(&Constants(&self.constants), 0) {
+            Ok(value) => Ok(value != 0),
+            Err(EvalError::Invalid(kind)) => Err(error(kind)),
+            Err(EvalError::Unresolved) => Err(error(AssemblerErrorKind::InvalidConditional(
+                "the condition may only use constants defined before it".to_string(),
+            ))),
+        }
+    }
+
+    /// Expands `.include "path"` in place. The included file shares this
+    /// file's macros and constants.
+    fn include(
+        &mut self,
+        line: &Line,
+        offset: usize,
+        argument: &str,
+        depth: usize,
+        out: &mut Vec<Line>,
+    ) -> Result<(), AssemblerError> {
+        let argument_offset = offset + line.code[offset..].find(argument).unwrap_or(0);
+        let error = |kind| line.error(kind, argument_offset, argument.len());
+        let Some(relative) = argument.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) else {
+            return Err(error(AssemblerErrorKind::InvalidInclude {
+                path: argument.to_string(),
+                reason: "expected a quoted path".to_string(),
+            }));
+        };
+
+        let including = Path::new(&*line.path).parent().unwrap_or(Path::new(""));
+        let path = including.join(relative);
+        let display: Rc<str> = Rc::from(path.display().to_string());
+        let canonical_path = canonical(&path);
+
+        if let Some(index) = self.include_stack.iter().position(|(open, _)| *open == canonical_path) {
+            let mut cycle: Vec<&str> = self.include_stack[index..].iter().map(|(_, name)| &**name).collect();
+            cycle.push(&display);
+            return Err(error(AssemblerErrorKind::CircularInclude(cycle.join(" -> "))));
+        }
+
+        let text = fs::read_to_string(&path).map_err(|e| {
+            error(AssemblerErrorKind::InvalidInclude {
+                path: display.to_string(),
+                reason: e.to_string(),
+            })
+        })?;
+
+        let lines = self.read_lines(&display, &text, line.expansion.clone());
+        self.include_stack.push((canonical_path, display));
+        self.expand(lines, depth, out);
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    /// Reads a `.macro name params...` header and its body up to the matching `.endm`.
+    fn define(
+        &mut self,
+        line: &Line,
+        offset: usize,
+        statement: &str,
+        lines: &mut impl Iterator<Item = Line>,
+    ) -> Result<(), AssemblerError> {
+        let header = &statement[".macro".len()..];
+        let mut words = header.split([',', ' ', '\t']).filter(|word| !word.is_empty());
+        let Some(name) = words.next() else {
+            return Err(line.error(
+                AssemblerErrorKind::InvalidMacro("missing macro name".to_string()),
+                offset,
+                ".macro".len(),
+            ));
+        };
+        let name_offset = offset + statement.find(name).unwrap_or(0);
+        let location = line.location(name_offset, name.len());
+
+        let params: Vec<String> = words.map(str::to_string).collect();
+        if let Some(param) = params.iter().find(|param| !is_identifier(param)) {
+            return Err(line.error(
+                AssemblerErrorKind::InvalidMacro(format!("invalid parameter name: {}", param)),
+                offset + statement.find(param.as_str()).unwrap_or(0),
+                param.len(),
+            ));
+        }
+
+        let mut body = Vec::new();
+        let mut nesting = 0;
+        loop {
+            let Some(body_line) = lines.next() else {
+                return Err(location.error(AssemblerErrorKind::UnterminatedMacro(name.to_string())));
+            };
+            match find_statement(&body_line.code).and_then(|(_, text)| text.split_whitespace().next()) {
+                Some(".macro") => nesting += 1,
+                Some(".endm") if nesting == 0 => break,
+                Some(".endm") => nesting -= 1,
+                _ => {}
+            }
+            body.push(body_line);
+        }
+
+        if let Some(previous) = self.macros.get(name) {
+            return Err(location.error(AssemblerErrorKind::DuplicateSymbol {
+                name: name.to_string(),
+                previous: format!("{}:{}", previous.location.path, previous.location.line),
+            }));
+        }
+
+        self.macros.insert(
+            name.to_string(),
+            Rc::new(Macro {
+                name: name.to_string(),
+                params,
+                body,
+                location,
+            }),
+        );
+        Ok(())
+    }
+
+    fn invoke(
+        &mut self,
+        definition: &Macro,
+        line: &Line,
+        offset: usize,
+        args: &str,
+        depth: usize,
+        out: &mut Vec<Line>,
+    ) -> Result<(), AssemblerError> {
+        let name = definition.name.as_str();
+        if depth >= MACRO_DEPTH_LIMIT {
+            return Err(line.error(
+                AssemblerErrorKind::MacroRecursionLimit {
+                    name: name.to_string(),
+                    limit: MACRO_DEPTH_LIMIT,
+                },
+                offset,
+                name.len(),
+            ));
+        }
+
+        let args = split_arguments(args);
+        if args.len() != definition.params.len() {
+            return Err(line.error(
+                AssemblerErrorKind::MacroArguments {
+                    name: name.to_string(),
+                    expected: definition.params.len(),
+                    found: args.len(),
+                },
+                offset,
+                name.len(),
+            ));
+        }
+
+        self.expansions += 1;
+        let id = self.expansions;
+
+        let mut chain = line.expansion.to_vec();
+        chain.push(Expansion {
+            name: name.to_string(),
+            path: line.path.clone(),
+            line: line.number,
+            column: line.text[..offset].chars().count() + 1,
+        });
+        let chain: Rc<[Expansion]> = Rc::from(chain);
+
+        let locals: Vec<&str> = definition
+            .body
+            .iter()
+            .filter_map(|body_line| label(&body_line.code))
+            .filter(|label| is_identifier(label))
+            .collect();
+
+        let expanded = definition
+            .body
+            .iter()
+            .map(|body_line| {
+                let text: Rc<str> = Rc::from(substitute(&body_line.code, &definition.params, &args, id, &locals));
+                Line {
+                    path: body_line.path.clone(),
+                    number: body_line.number,
+                    text: text.clone(),
+                    code: text,
+                    expansion: chain.clone(),
+                }
+            })
+            .collect();
+
+        self.expand(expanded, depth + 1, out);
+        Ok(())
+    }
+}
+
+/// The path used to recognise a file that is already being read. Sources
+/// that do not exist on disk (given to the library directly) use their name.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[inline]
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'
+}
+
+/// Whether `word` can name a symbol, macro or macro parameter: a letter, `_`
+/// or `.` followed by letters, digits, `_` or `.`.
+pub(crate) fn is_identifier(word: &str) -> bool {
+    word.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_' || ch == '.')
+        && word.chars().all(is_identifier_char)
+}
+
+/// The label defined at the start of a line, if any.
+fn label(text: &str) -> Option<&str> {
+    let code = text.trim();
+    let colon = code.find(':')?;
+    let label = code[..colon].trim();
+    (!label.contains(char::is_whitespace) && !label.is_empty()).then_some(label)
+}
+
+/// The statement in a line's code after any leading `label:`, and its byte
+/// offset in the line. `None` for blank and comment-only lines.
+fn find_statement(code: &str) -> Option<(usize, &str)> {
+    let mut offset = code.len() - code.trim_start().len();
+    if label(code).is_some() {
+        let colon = offset + code[offset..].find(':')? + 1;
+        let rest = &code[colon..];
+        offset = colon + (rest.len() - rest.trim_start().len());
+    }
+
+    let statement = code[offset..].trim_end();
+    (!statement.is_empty()).then_some((offset, statement))
+}
+
+/// Blanks out the comments in one line of source: `;` and `//` to the end
+/// of the line, `#` when it starts the line, and `/* */` blocks, which may
+/// span lines (`open` carries the start of an unclosed one to the next line).
+/// Comment markers inside string and character literals are left alone.
+///
+/// Every comment byte becomes a space, so the result has the same length as
+/// `text` and byte offsets into it point at the same source in `text`.
+fn strip_comments(text: &str, index: usize, open: &mut Option<(usize, usize)>) -> String {
+    let mut code = String::with_capacity(text.len());
+    let first = text.len() - text.trim_start().len();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut chars = text.char_indices();
+
+    while let Some((i, ch)) = chars.next() {
+        let rest = &text[i..];
+        if open.is_some() {
+            if rest.starts_with("*/") {
+                *open = None;
+                chars.next();
+                code.push_str("  ");
+            } else {
+                code.extend(std::iter::repeat_n(' ', ch.len_utf8()));
+            }
+            continue;
+        }
+        if let Some(delimiter) = quote {
+            code.push(ch);
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if ch == delimiter => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+
+        match ch {
+            '"' | '\'' => {
+                quote = Some(ch);
+                code.push(ch);
+            }
+            ';' => break,
+            '/' if rest.starts_with("//") => break,
+            '#' if i == first => break,
+            '/' if rest.starts_with("/*") => {
+                *open = Some((index, i));
+                chars.next();
+                code.push_str("  ");
+            }
+            _ => code.push(ch),
+        }
+    }
+
+    code.extend(std::iter::repeat_n(' ', text.len() - code.len()));
+    code
+}
+
+/// Splits macro arguments on commas that are not inside brackets, parentheses
+/// or quotes.
+fn split_arguments(args: &str) -> Vec<String> {
+    if args.trim().is_empty() {
+        return Vec::new();
+    }
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for ch in args.chars() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' | '(' if !in_string => depth += 1,
+            ']' | ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(ch);
+    }
+    parts.push(current.trim().to_string());
+    parts
+}
+
+/// Replaces `\param` with its argument, `\@` with the expansion number, and
+/// renames the macro's own labels so each expansion gets a fresh copy.
+fn substitute(text: &str, params: &[String], args: &[String], id: usize, locals: &[&str]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut i = 0;
+
+    while let Some(ch) = text[i..].chars().next() {
+        if ch == '\\' {
+            let rest = &text[i + 1..];
+            if rest.starts_with('@') {
+                out.push_str(&id.to_string());
+                i += 2;
+                continue;
+            }
+            let len = rest.find(|c: char| !is_identifier_char(c)).unwrap_or(rest.len());
+            if let Some(index) = params.iter().position(|param| param == &rest[..len]) {
+                out.push_str(&args[index]);
+                i += 1 + len;
+                continue;
+            }
+            out.push(ch);
+            i += 1;
+            if in_string && let Some(escaped) = rest.chars().next() {
+                out.push(escaped);
+                i += escaped.len_utf8();
+            }
+            continue;
+        }
+
+        if ch == '"' {
+            in_string = !in_string;
+        } else if !in_string && is_identifier_char(ch) {
+            let len = text[i..].find(|c: char| !is_identifier_char(c)).unwrap_or(text.len() - i);
+            let word = &text[i..i + len];
+            if locals.contains(&word) {
+                out.push_str(&format!("{}.{}", word, id));
+            } else {
+                out.push_str(word);
+            }
+            i += len;
+            continue;
+        }
+
+        out.push(ch);
+        i += ch.len_utf8();
+    }
+    out
+}
@@ -1,34 +1,179 @@
 use std::io;
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufWriter, Write};
+use std::time::{Duration, Instant};
+use crate::fam::FamImage;
+use crate::load_error::{LoadError, LoadErrorKind};
 use crate::op_codes::OpCode;
-use crate::runtime_error::RuntimeError;
+use crate::runtime_error::{RuntimeError, RuntimeErrorKind};
 
 #[derive(Debug, Clone)]
 pub struct Instruction {
-    pub(crate) opcode: OpCode,
-    pub(crate) rd: u8,
-    pub(crate) rs1: u8,
-    pub(crate) rs2: u8,
-    pub(crate) immediate: i32,
-    pub(crate) label: Option<String>,
+    pub opcode: OpCode,
+    pub rd: u8,
+    pub rs1: u8,
+    pub rs2: u8,
+    pub immediate: i32,
+    pub mode: OperandMode,
+}
+
+/// Selects whether an instruction's register-or-immediate operand is read from
+/// a register or taken from the immediate field. Encoded in the top bit of the
+/// opcode byte, so every register (including r0) can be used as an operand.
+///
+/// - MOV: `rs1` or the immediate
+/// - ADD/SUB/MUL/DIV/AND/OR/XOR/CMP: `rs2` or the immediate
+/// - LEA/LOAD/LOADB/STORE: `rs1 + immediate` or the absolute immediate
+/// - INPUT: the input mode comes from `rs1` or the immediate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandMode {
+    Register,
+    Immediate,
+}
+
+/// Opcode-byte bit that marks an instruction as using `OperandMode::Immediate`.
+pub(crate) const IMMEDIATE_MODE_BIT: u8 = 0x80;
+
+impl Instruction {
+    /// Splits an 8-byte encoded instruction into its two code-memory words:
+    /// `opcode | rd | rs1 | rs2` packed high to low, then the immediate.
+    #[inline]
+    pub fn words_from_bytes(bytes: &[u8]) -> (i32, i32) {
+        (
+            i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        )
+    }
+
+    /// Decodes the two code-memory words of an instruction, or `None` if the
+    /// opcode is not recognized.
+    #[inline]
+    pub fn decode(word1: i32, word2: i32) -> Option<Instruction> {
+        let opcode_byte = ((word1 >> 24) & 0xFF) as u8;
+        let opcode = OpCode::from_u8(opcode_byte & !IMMEDIATE_MODE_BIT)?;
+        let mode = if opcode_byte & IMMEDIATE_MODE_BIT != 0 {
+            OperandMode::Immediate
+        } else {
+            OperandMode::Register
+        };
+        let rd = ((word1 >> 16) & 0xFF) as u8;
+        let rs1 = ((word1 >> 8) & 0xFF) as u8;
+        let rs2 = (word1 & 0xFF) as u8;
+        let immediate = word2;
+
+        Some(Instruction {
+            opcode,
+            rd,
+            rs1,
+            rs2,
+            immediate,
+            mode,
+        })
+    }
+
+    #[inline]
+    pub fn encode(&self) -> [u8; 8] {
+        let imm = self.immediate.to_le_bytes();
+        let mode_bit = match self.mode {
+            OperandMode::Register => 0,
+            OperandMode::Immediate => IMMEDIATE_MODE_BIT,
+        };
+        [
+            self.opcode as u8 | mode_bit,
+            self.rd,
+            self.rs1,
+            self.rs2,
+            imm[0],
+            imm[1],
+            imm[2],
+            imm[3],
+        ]
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct StackFrame {
+    /// Index of the instruction, as `Runtime::pc()` counts them.
     pub pc: usize,
     pub instruction: String,
 }
 
-pub struct Runtime {
-    registers: [i32; 32],
-    memory: [i32; 1024],
+/// Bounds on how long `Runtime::run_with_limits` lets a program run. A limit
+/// left as `None` does not apply.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    pub max_steps: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl ExecutionLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the program once it has executed `steps` instructions.
+    pub fn with_max_steps(mut self, steps: usize) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    /// Stops the program once it has run for `timeout` of wall-clock time.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// How many instructions run between checks of the wall-clock timeout.
+const TIMEOUT_CHECK_INTERVAL: usize = 1024;
+
+/// Byte address at which the data section is loaded into data memory.
+pub const DATA_BASE: usize = 512;
+
+/// Number of general-purpose registers, `r0` to `r31`.
+pub const REGISTER_COUNT: usize = 32;
+
+/// The stack pointer register, `r31` (`sp` in assembly). It holds the byte
+/// address of the value on top of the stack.
+pub const STACK_POINTER: usize = 31;
+
+/// The frame pointer register, `r30` (`fp` in assembly). The VM does not use
+/// it; it is reserved by convention for addressing a function's stack slots.
+pub const FRAME_POINTER: usize = 30;
+
+/// Default size of data memory in bytes, used unless `with_memory_size` overrides it.
+pub const DEFAULT_MEMORY_SIZE: usize = 4096;
+
+/// Largest data memory size in bytes: the stack pointer starts at the end of
+/// memory, so the size has to fit in a register. `with_memory_size` caps
+/// larger sizes to this.
+pub const MAX_MEMORY_SIZE: usize = i32::MAX as usize & !3;
+
+/// The virtual machine. `R` is where INPUT reads from and `W` is where PRINT and
+/// PRINTC write to; `Runtime::new()` uses stdin and stdout, and `with_io`
+/// accepts any reader and writer, such as in-memory buffers.
+pub struct Runtime<R = io::StdinLock<'static>, W = BufWriter<io::Stdout>> {
+    registers: [i32; REGISTER_COUNT],
+    code: Vec<i32>,
+    memory: Vec<i32>,
+    /// The words at the end of data memory, last word first, so the stack
+    /// grows this buffer instead of allocating all of memory below it.
     stack: Vec<i32>,
+    memory_limit: usize,
+    stack_size: Option<usize>,
+    halt_required: bool,
     pc: usize,
     flags: Flags,
     running: bool,
     call_stack: Vec<usize>,
     instruction_count: usize,
-    output_buffer: BufWriter<io::Stdout>,
+    input: R,
+    output: W,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -40,11 +185,25 @@ struct Flags {
 }
 
 impl Runtime {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
+        Runtime::with_io(io::stdin().lock(), BufWriter::new(io::stdout()))
+    }
+}
+
+impl<R: BufRead, W: Write> Runtime<R, W> {
+    /// Creates a runtime that reads INPUT from `input` and writes program
+    /// output to `output`.
+    pub fn with_io(input: R, output: W) -> Self {
+        let mut registers = [0; REGISTER_COUNT];
+        registers[STACK_POINTER] = DEFAULT_MEMORY_SIZE as i32;
         Runtime {
-            registers: [0; 32],
-            memory: [0; 1024],
+            registers,
+            code: Vec::new(),
+            memory: Vec::new(),
             stack: Vec::new(),
+            memory_limit: DEFAULT_MEMORY_SIZE / 4,
+            stack_size: None,
+            halt_required: false,
             pc: 0,
             flags: Flags {
                 zero: false,
@@ -55,30 +214,239 @@ impl Runtime {
             running: true,
             call_stack: Vec::new(),
             instruction_count: 0,
-            output_buffer: BufWriter::new(io::stdout()),
+            input,
+            output,
+        }
+    }
+
+    /// Sets the data memory size in bytes (rounded up to whole words, at most
+    /// 2 GiB). Memory is allocated lazily and grows on write until it reaches
+    /// this limit. The stack pointer is moved to the new end of memory.
+    pub fn with_memory_size(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes.min(MAX_MEMORY_SIZE).div_ceil(4);
+        self.memory.truncate(self.memory_limit);
+        self.stack.clear();
+        self.registers[STACK_POINTER] = self.memory_size() as i32;
+        self
+    }
+
+    /// Sets how many bytes at the end of data memory the stack may use
+    /// (rounded up to whole words). Defaults to a quarter of data memory.
+    pub fn with_stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes.min(MAX_MEMORY_SIZE).next_multiple_of(4));
+        self
+    }
+
+    /// When `required` is true, a program must stop with HALT: running past
+    /// its last instruction is a `PcOutOfBounds` error instead of a normal end.
+    pub fn with_halt_required(mut self, required: bool) -> Self {
+        self.halt_required = required;
+        self
+    }
+
+    /// The writer program output goes to.
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    /// Consumes the runtime and returns its writer, e.g. to inspect captured output.
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
+    /// Size of data memory in bytes, including words that have not been allocated yet.
+    #[inline]
+    pub fn memory_size(&self) -> usize {
+        self.memory_limit * 4
+    }
+
+    /// Lowest byte address the stack may grow down to. The stack occupies the
+    /// end of data memory, from here up to `memory_size()`.
+    #[inline]
+    pub fn stack_limit(&self) -> usize {
+        let stack_size = self.stack_size.unwrap_or(self.memory_size() / 4 / 4 * 4);
+        self.memory_size().saturating_sub(stack_size)
+    }
+
+    /// Number of words currently on the stack.
+    #[inline]
+    fn stack_depth(&self) -> usize {
+        match usize::try_from(self.registers[STACK_POINTER]) {
+            Ok(sp) => self.memory_size().saturating_sub(sp) / 4,
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns the value of register `index`, or `None` if there is no such register.
+    #[inline]
+    pub fn register(&self, index: usize) -> Option<i32> {
+        self.registers.get(index).copied()
+    }
+
+    /// Sets register `index`. Returns `false` if there is no such register.
+    #[inline]
+    pub fn set_register(&mut self, index: usize, value: i32) -> bool {
+        match self.registers.get_mut(index) {
+            Some(register) => {
+                *register = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copies `len` bytes of data memory starting at byte address `addr`, or
+    /// returns `None` if the range is outside memory.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let end = addr.checked_add(len)?;
+        if end > self.memory_size() {
+            return None;
+        }
+        (addr..end).map(|a| self.read_byte(a)).collect()
+    }
+
+    /// Writes `bytes` into data memory starting at byte address `addr`. Nothing
+    /// is written and `false` is returned if the range is outside memory.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> bool {
+        match addr.checked_add(bytes.len()) {
+            Some(end) if end <= self.memory_size() => {}
+            _ => return false,
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.write_byte(addr + i, byte);
+        }
+        true
+    }
+
+    /// Index of the next instruction to execute.
+    #[inline]
+    pub fn pc(&self) -> usize {
+        self.pc / 2
+    }
+
+    /// Whether the program can still execute: it has not halted or run off
+    /// the end of the code. When HALT is required, running off the end is
+    /// an error reported by the next step instead.
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.running && (self.pc != self.code.len() || self.halt_required)
+    }
+
+    /// Number of instructions executed so far.
+    #[inline]
+    pub fn instruction_count(&self) -> usize {
+        self.instruction_count
+    }
+
+    #[inline]
+    fn read_word(&self, index: usize) -> Option<i32> {
+        if index >= self.memory_limit {
+            return None;
+        }
+        let from_end = self.memory_limit - 1 - index;
+        Some(self.memory.get(index).or_else(|| self.stack.get(from_end)).copied().unwrap_or(0))
+    }
+
+    /// Writes word `index`, growing whichever of `memory` (from the start) and
+    /// `stack` (from the end) needs the fewest new words to reach it. The two
+    /// never overlap, so every word lives in exactly one of them.
+    #[inline]
+    fn write_word(&mut self, index: usize, value: i32) -> bool {
+        if index >= self.memory_limit {
+            return false;
+        }
+        let from_end = self.memory_limit - 1 - index;
+        if index < self.memory.len() {
+            self.memory[index] = value;
+        } else if from_end < self.stack.len() {
+            self.stack[from_end] = value;
+        } else if index - self.memory.len() <= from_end - self.stack.len() {
+            let free = self.memory_limit - self.stack.len();
+            let new_len = (index + 1).max(self.memory.len() * 2).min(free);
+            self.memory.resize(new_len, 0);
+            self.memory[index] = value;
+        } else {
+            let free = self.memory_limit - self.memory.len();
+            let new_len = (from_end + 1).max(self.stack.len() * 2).min(free);
+            self.stack.resize(new_len, 0);
+            self.stack[from_end] = value;
         }
+        true
+    }
+
+    #[inline]
+    fn read_byte(&self, addr: usize) -> Option<u8> {
+        let word = self.read_word(addr / 4)?;
+        Some(((word >> ((addr % 4) * 8)) & 0xFF) as u8)
     }
 
+    #[inline]
+    fn write_byte(&mut self, addr: usize, byte: u8) -> bool {
+        let Some(current) = self.read_word(addr / 4) else {
+            return false;
+        };
+        let offset = addr % 4;
+        let mask = !(0xFF << (offset * 8));
+        self.write_word(addr / 4, (current & mask) | ((byte as i32) << (offset * 8)))
+    }
+
+    /// Sets Z/N from a logical result. Logical operations never carry or
+    /// overflow, so C and V are cleared.
     #[inline]
     fn set_flags(&mut self, value: i32) {
         self.flags.zero = value == 0;
         self.flags.negative = value < 0;
+        self.flags.carry = false;
+        self.flags.overflow = false;
     }
 
-    fn create_stack_trace(&self) -> Vec<StackFrame> {
+    /// Sets all four flags from an arithmetic result. `carry` is the unsigned
+    /// carry out (for SUB/CMP, the borrow), `overflow` the signed overflow.
+    #[inline]
+    fn set_arithmetic_flags(&mut self, value: i32, carry: bool, overflow: bool) {
+        self.flags.zero = value == 0;
+        self.flags.negative = value < 0;
+        self.flags.carry = carry;
+        self.flags.overflow = overflow;
+    }
+
+    /// Whether a conditional jump is taken. Signed comparisons use N and V so
+    /// they stay correct when the CMP subtraction overflows; unsigned ones use C.
+    #[inline]
+    fn condition_holds(&self, opcode: OpCode) -> bool {
+        let Flags { zero, negative, carry, overflow } = self.flags;
+        match opcode {
+            OpCode::JEQ => zero,
+            OpCode::JNE => !zero,
+            OpCode::JLT => negative != overflow,
+            OpCode::JGE => negative == overflow,
+            OpCode::JGT => !zero && negative == overflow,
+            OpCode::JLE => zero || negative != overflow,
+            OpCode::JCS | OpCode::JB => carry,
+            OpCode::JCC => !carry,
+            OpCode::JVS => overflow,
+            OpCode::JVC => !overflow,
+            OpCode::JA => !carry && !zero,
+            _ => true,
+        }
+    }
+
+    fn create_stack_trace(&self, pc: usize) -> Vec<StackFrame> {
         let mut stack_trace = Vec::new();
 
-        if let Some(current_instruction) = self.get_instruction_at_pc(self.pc - 2) {
+        if let Some(current_instruction) = self.get_instruction_at_pc(pc) {
             stack_trace.push(StackFrame {
-                pc: self.pc - 2,
+                pc: pc / 2,
                 instruction: format!("{:?}", current_instruction.opcode),
             });
         }
 
-        for &call_pc in &self.call_stack {
+        // `call_stack` holds return addresses; each frame shows its CALL.
+        for &return_pc in &self.call_stack {
+            let call_pc = return_pc - 2;
             if let Some(instruction) = self.get_instruction_at_pc(call_pc) {
                 stack_trace.push(StackFrame {
-                    pc: call_pc,
+                    pc: call_pc / 2,
                     instruction: format!("{:?}", instruction.opcode),
                 });
             }
@@ -87,303 +455,475 @@ impl Runtime {
         stack_trace
     }
 
+    #[inline]
     fn get_instruction_at_pc(&self, pc: usize) -> Option<Instruction> {
-        if pc >= self.memory.len() - 1 {
+        if pc >= self.code.len().saturating_sub(1) {
             return None;
         }
 
-        let word1 = self.memory[pc];
-        let word2 = self.memory[pc + 1];
+        Instruction::decode(self.code[pc], self.code[pc + 1])
+    }
 
-        let opcode = OpCode::from_u8(((word1 >> 24) & 0xFF) as u8)?;
-        let rd = ((word1 >> 16) & 0xFF) as u8;
-        let rs1 = ((word1 >> 8) & 0xFF) as u8;
-        let rs2 = (word1 & 0xFF) as u8;
-        let immediate = word2;
+    /// Computes `rs1 + immediate` for memory operands, or just the immediate in
+    /// immediate mode. Widened to `i64` so bounds checks can reject negatives.
+    #[inline]
+    fn effective_address(&self, instruction: &Instruction) -> i64 {
+        match instruction.mode {
+            OperandMode::Register => {
+                self.registers[instruction.rs1 as usize] as i64 + instruction.immediate as i64
+            }
+            OperandMode::Immediate => instruction.immediate as i64,
+        }
+    }
 
-        Some(Instruction {
-            opcode,
-            rd,
-            rs1,
-            rs2,
-            immediate,
-            label: None,
-        })
+    /// The word index for a LOAD or STORE. Their addresses are byte addresses,
+    /// like those of LOADB and data labels, and must be a multiple of 4.
+    fn word_index(&self, instruction: &Instruction) -> Result<usize, RuntimeError> {
+        let addr = self.effective_address(instruction);
+        if addr < 0 || addr + 4 > self.memory_size() as i64 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::MemoryOutOfBounds,
+                format!("Memory access out of bounds: address {} (max: {})",
+                        addr, self.memory_size().saturating_sub(4)),
+                instruction.clone(),
+            ));
+        }
+        if addr % 4 != 0 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::MisalignedAccess,
+                format!("Misaligned word access: address {} is not a multiple of 4", addr),
+                instruction.clone(),
+            ));
+        }
+        Ok(addr as usize / 4)
+    }
+
+    /// The word index of the stack slot at byte address `sp`.
+    fn stack_slot(&self, sp: i64, instruction: &Instruction) -> Result<usize, RuntimeError> {
+        if sp < 0 || sp + 4 > self.memory_size() as i64 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::MemoryOutOfBounds,
+                format!("Stack pointer {} is outside memory (size: {})", sp, self.memory_size()),
+                instruction.clone(),
+            ));
+        }
+        if sp % 4 != 0 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::MisalignedAccess,
+                format!("Misaligned stack pointer: {} is not a multiple of 4", sp),
+                instruction.clone(),
+            ));
+        }
+        Ok(sp as usize / 4)
     }
 
-    fn runtime_error(&self, message: String, instruction: Instruction) -> RuntimeError {
+    /// Moves SP down a word and stores `value` there, failing rather than
+    /// growing the stack past its limit.
+    fn push(&mut self, value: i32, instruction: &Instruction) -> Result<(), RuntimeError> {
+        let sp = self.registers[STACK_POINTER] as i64 - 4;
+        if sp < self.stack_limit() as i64 {
+            return Err(self.runtime_error(
+                RuntimeErrorKind::StackOverflow,
+                format!("Stack overflow: pushing would move SP to {}, below the stack limit {}",
+                        sp, self.stack_limit()),
+                instruction.clone(),
+            ));
+        }
+        let index = self.stack_slot(sp, instruction)?;
+        self.write_word(index, value);
+        self.registers[STACK_POINTER] = sp as i32;
+        Ok(())
+    }
+
+    /// Reads the word at SP and moves SP up past it. Returns `None` when the
+    /// stack is empty.
+    fn pop(&mut self, instruction: &Instruction) -> Result<Option<i32>, RuntimeError> {
+        let sp = self.registers[STACK_POINTER] as i64;
+        if sp >= self.memory_size() as i64 {
+            return Ok(None);
+        }
+        let index = self.stack_slot(sp, instruction)?;
+        let value = self.read_word(index).unwrap_or(0);
+        self.registers[STACK_POINTER] = (sp + 4) as i32;
+        Ok(Some(value))
+    }
+
+    /// Moves the PC to instruction `target`. Targets are checked when the
+    /// program is loaded, so this only guards against wrapping around.
+    fn jump(&mut self, target: i32, instruction: &Instruction) -> Result<(), RuntimeError> {
+        match usize::try_from(target).ok().filter(|&target| target <= self.code.len() / 2) {
+            Some(target) => {
+                self.pc = target * 2;
+                Ok(())
+            }
+            None => Err(self.runtime_error(
+                RuntimeErrorKind::PcOutOfBounds { pc: target as u32 as usize },
+                format!("Jump target {} is outside the program ({} instructions)", target, self.code.len() / 2),
+                instruction.clone(),
+            )),
+        }
+    }
+
+    /// The second source operand of ALU instructions and CMP: `rs2` or the immediate.
+    #[inline]
+    fn second_operand(&self, instruction: &Instruction) -> i32 {
+        match instruction.mode {
+            OperandMode::Register => self.registers[instruction.rs2 as usize],
+            OperandMode::Immediate => instruction.immediate,
+        }
+    }
+
+    fn runtime_error(&self, kind: RuntimeErrorKind, message: String, instruction: Instruction) -> RuntimeError {
         RuntimeError::new(
+            kind,
             message,
-            self.pc - 2,
-            instruction,
-            self.create_stack_trace(),
+            self.pc / 2 - 1,
+            Some(instruction),
+            self.create_stack_trace(self.pc - 2),
+            self.instruction_count,
         )
     }
 
-    pub(crate) fn load_program(&mut self, bytecode: &[u8]) {
-        let mut data_end = 0;
-        for i in (0..bytecode.len()).step_by(8) {
-            if i + 7 < bytecode.len() {
-                let chunk = &bytecode[i..i + 8];
-                if chunk == [0, 0, 0, 0, 0, 0, 0, 0] {
-                    data_end = i;
-                    break;
-                }
-            }
+    fn output_error(&self, error: io::Error, instruction: &Instruction) -> RuntimeError {
+        self.runtime_error(
+            RuntimeErrorKind::Io,
+            format!("Failed to write output: {}", error),
+            instruction.clone(),
+        )
+    }
+
+    #[inline]
+    fn flush_output(&mut self, instruction: &Instruction) -> Result<(), RuntimeError> {
+        self.output.flush().map_err(|e| self.output_error(e, instruction))
+    }
+
+    /// Loads a program into the VM. Instructions go into code memory, which is
+    /// separate from the data memory that LOAD/STORE and friends address, so a
+    /// large text section can never overwrite the data section.
+    ///
+    /// Loading resets the VM: registers, flags, data memory, the call stack and
+    /// the instruction count start fresh, so a runtime can run several programs
+    /// in turn. Configuration from the `with_*` builders is kept. If loading
+    /// fails, the runtime is left as it was.
+    pub fn load_program(&mut self, bytecode: &[u8]) -> Result<(), LoadError> {
+        let image = FamImage::from_bytes(bytecode)?;
+
+        if DATA_BASE + image.data.len() > self.stack_limit() {
+            return Err(LoadError::new(LoadErrorKind::DataTooLarge, format!(
+                "Data section too large: {} bytes at address {} overlaps the stack, which starts at {} \
+                 (memory size {} bytes)",
+                image.data.len(), DATA_BASE, self.stack_limit(), self.memory_size()
+            )));
         }
 
-        for (i, &byte) in bytecode[..data_end].iter().enumerate() {
-            if i + 512 < self.memory.len() {
-                let addr = (i + 512) / 4;
-                let offset = (i + 512) % 4;
-                let current = self.memory[addr];
-                let mask = !(0xFF << (offset * 8));
-                let new_val = (current & mask) | ((byte as i32) << (offset * 8));
-                self.memory[addr] = new_val;
-            }
-        }
-
-        if data_end + 8 <= bytecode.len() {
-            let start_bytes = &bytecode[data_end..data_end + 4];
-            let start_pc = u32::from_le_bytes([
-                start_bytes[0],
-                start_bytes[1],
-                start_bytes[2],
-                start_bytes[3],
-            ]) as usize;
-            self.pc = start_pc * 2;
-
-            let instructions_start = data_end + 8;
-            for (i, chunk) in bytecode[instructions_start..].chunks(8).enumerate() {
-                if chunk.len() == 8 {
-                    let addr = i * 2;
-                    if addr < self.memory.len() {
-                        let opcode = chunk[0];
-                        let rd = chunk[1];
-                        let rs1 = chunk[2];
-                        let rs2 = chunk[3];
-                        let immediate =
-                            i32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
-
-                        self.memory[addr] = ((opcode as i32) << 24)
-                            | ((rd as i32) << 16)
-                            | ((rs1 as i32) << 8)
-                            | (rs2 as i32);
-                        self.memory[addr + 1] = immediate;
-                    }
+        let start_pc = image.entry as usize;
+        let text = &image.text;
+        if !text.len().is_multiple_of(8) {
+            return Err(LoadError::new(LoadErrorKind::Truncated, format!(
+                "Truncated text section: {} bytes is not a whole number of 8-byte instructions",
+                text.len()
+            )));
+        }
+
+        let instruction_count = text.len() / 8;
+        if start_pc >= instruction_count && instruction_count > 0 {
+            return Err(LoadError::new(LoadErrorKind::InvalidStart, format!(
+                "Start address {} is outside the program ({} instructions)",
+                start_pc, instruction_count
+            )));
+        }
+
+        let mut code = Vec::with_capacity(instruction_count * 2);
+        for chunk in text.chunks_exact(8) {
+            let (word1, word2) = Instruction::words_from_bytes(chunk);
+            code.push(word1);
+            code.push(word2);
+        }
+        Self::verify(&code)?;
+
+        self.registers = [0; REGISTER_COUNT];
+        self.registers[STACK_POINTER] = self.memory_size() as i32;
+        self.memory.clear();
+        self.stack.clear();
+        self.flags = Flags {
+            zero: false,
+            negative: false,
+            carry: false,
+            overflow: false,
+        };
+        self.running = true;
+        self.call_stack.clear();
+        self.instruction_count = 0;
+        for (i, &byte) in image.data.iter().enumerate() {
+            self.write_byte(DATA_BASE + i, byte);
+        }
+        self.code = code;
+        self.pc = start_pc * 2;
+
+        Ok(())
+    }
+
+    /// Checks the fields `execute` relies on in every instruction that
+    /// decodes: register numbers and jump targets. Words that do not decode
+    /// are left to fail as illegal instructions if they are ever reached.
+    fn verify(code: &[i32]) -> Result<(), LoadError> {
+        let instruction_count = code.len() / 2;
+        for (index, words) in code.chunks_exact(2).enumerate() {
+            let Some(instruction) = Instruction::decode(words[0], words[1]) else {
+                continue;
+            };
+
+            for register in [instruction.rd, instruction.rs1, instruction.rs2] {
+                if register as usize >= REGISTER_COUNT {
+                    let kind = LoadErrorKind::InvalidRegister { instruction: index, register };
+                    return Err(LoadError::new(kind, format!(
+                        "Invalid instruction {} ({:?}): register r{} does not exist (registers are r0 to r31)",
+                        index, instruction.opcode, register
+                    )));
                 }
             }
+
+            let target = instruction.immediate;
+            if instruction.opcode.is_branch() && (target < 0 || target as usize > instruction_count) {
+                let kind = LoadErrorKind::InvalidJumpTarget { instruction: index, target };
+                return Err(LoadError::new(kind, format!(
+                    "Invalid instruction {} ({:?}): jump target {} is outside the program ({} instructions)",
+                    index, instruction.opcode, target, instruction_count
+                )));
+            }
         }
+        Ok(())
     }
 
+    /// The instruction at the PC, or `None` if the program has ended by
+    /// reaching the end of the code. Any other PC without a valid instruction
+    /// is an error.
     #[inline]
-    fn fetch(&mut self) -> Option<Instruction> {
-        if self.pc >= self.memory.len() - 1 {
-            return None;
+    fn next_instruction(&self) -> Result<Option<Instruction>, RuntimeError> {
+        if let Some(instruction) = self.get_instruction_at_pc(self.pc) {
+            return Ok(Some(instruction));
         }
 
-        let word1 = self.memory[self.pc];
-        let word2 = self.memory[self.pc + 1];
+        let (kind, message) = if self.pc < self.code.len() {
+            let raw_word = self.code[self.pc] as u32;
+            (
+                RuntimeErrorKind::IllegalInstruction { pc: self.pc / 2, raw_word },
+                format!("Illegal instruction: 0x{:08x} is not a valid instruction", raw_word),
+            )
+        } else if self.pc == self.code.len() {
+            if !self.halt_required {
+                return Ok(None);
+            }
+            (
+                RuntimeErrorKind::PcOutOfBounds { pc: self.pc / 2 },
+                "Program ran past its last instruction without reaching HALT".to_string(),
+            )
+        } else {
+            (
+                RuntimeErrorKind::PcOutOfBounds { pc: self.pc / 2 },
+                format!("PC {} is outside the program ({} instructions)", self.pc / 2, self.code.len() / 2),
+            )
+        };
+        Err(RuntimeError::new(
+            kind,
+            message,
+            self.pc / 2,
+            None,
+            self.create_stack_trace(self.pc),
+            self.instruction_count,
+        ))
+    }
 
-        let opcode = OpCode::from_u8(((word1 >> 24) & 0xFF) as u8)?;
-        let rd = ((word1 >> 16) & 0xFF) as u8;
-        let rs1 = ((word1 >> 8) & 0xFF) as u8;
-        let rs2 = (word1 & 0xFF) as u8;
-        let immediate = word2;
+    #[inline]
+    fn fetch(&mut self) -> Result<Option<Instruction>, RuntimeError> {
+        let instruction = self.next_instruction()?;
+        if instruction.is_some() {
+            self.advance();
+        }
+        Ok(instruction)
+    }
 
+    /// Moves past the instruction at the PC once it has been fetched.
+    #[inline]
+    fn advance(&mut self) {
         self.pc += 2;
         self.instruction_count += 1;
-
-        Some(Instruction {
-            opcode,
-            rd,
-            rs1,
-            rs2,
-            immediate,
-            label: None,
-        })
     }
 
     #[inline]
     fn execute(&mut self, instruction: Instruction) -> Result<(), RuntimeError> {
         match instruction.opcode {
             OpCode::MOV => {
-                if instruction.rs1 != 0 {
-                    self.registers[instruction.rd as usize] =
-                        self.registers[instruction.rs1 as usize];
-                } else {
-                    self.registers[instruction.rd as usize] = instruction.immediate;
-                }
+                self.registers[instruction.rd as usize] = match instruction.mode {
+                    OperandMode::Register => self.registers[instruction.rs1 as usize],
+                    OperandMode::Immediate => instruction.immediate,
+                };
             }
             OpCode::ADD => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
-                self.registers[instruction.rd as usize] = val1.wrapping_add(val2);
+                let val2 = self.second_operand(&instruction);
+                let (result, carry) = (val1 as u32).overflowing_add(val2 as u32);
+                let overflow = val1.overflowing_add(val2).1;
+                self.registers[instruction.rd as usize] = result as i32;
+                self.set_arithmetic_flags(result as i32, carry, overflow);
             }
             OpCode::SUB => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
-                self.registers[instruction.rd as usize] = val1.wrapping_sub(val2);
+                let val2 = self.second_operand(&instruction);
+                let (result, overflow) = val1.overflowing_sub(val2);
+                self.registers[instruction.rd as usize] = result;
+                self.set_arithmetic_flags(result, (val1 as u32) < (val2 as u32), overflow);
             }
             OpCode::MUL => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
-                self.registers[instruction.rd as usize] = val1.wrapping_mul(val2);
+                let val2 = self.second_operand(&instruction);
+                let (result, overflow) = val1.overflowing_mul(val2);
+                let carry = (val1 as u32).overflowing_mul(val2 as u32).1;
+                self.registers[instruction.rd as usize] = result;
+                self.set_arithmetic_flags(result, carry, overflow);
             }
             OpCode::DIV => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
+                let val2 = self.second_operand(&instruction);
 
                 if val2 == 0 {
                     return Err(self.runtime_error(
+                        RuntimeErrorKind::DivisionByZero,
                         "Division by zero".to_string(),
                         instruction,
                     ));
                 }
 
-                let result = val1 / val2;
+                let (result, overflow) = val1.overflowing_div(val2);
                 self.registers[instruction.rd as usize] = result;
-                self.set_flags(result);
+                self.set_arithmetic_flags(result, false, overflow);
             }
             OpCode::CMP => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
-                let result = val1 - val2;
-                self.set_flags(result);
+                let val2 = self.second_operand(&instruction);
+                let (result, overflow) = val1.overflowing_sub(val2);
+                self.set_arithmetic_flags(result, (val1 as u32) < (val2 as u32), overflow);
             }
             OpCode::JMP => {
-                self.output_buffer.flush().unwrap();
-                self.pc = (instruction.immediate as usize) * 2;
+                self.flush_output(&instruction)?;
+                self.jump(instruction.immediate, &instruction)?;
             }
-            OpCode::JEQ => {
-                if self.flags.zero {
-                    self.pc = (instruction.immediate as usize) * 2;
-                }
-            }
-            OpCode::JNE => {
-                if !self.flags.zero {
-                    self.pc = (instruction.immediate as usize) * 2;
-                }
-            }
-            OpCode::JLT => {
-                if self.flags.negative {
-                    self.pc = (instruction.immediate as usize) * 2;
-                }
-            }
-            OpCode::JGT => {
-                if !self.flags.negative && !self.flags.zero {
-                    self.pc = (instruction.immediate as usize) * 2;
+            OpCode::JEQ
+            | OpCode::JNE
+            | OpCode::JLT
+            | OpCode::JGT
+            | OpCode::JLE
+            | OpCode::JGE
+            | OpCode::JCS
+            | OpCode::JCC
+            | OpCode::JVS
+            | OpCode::JVC
+            | OpCode::JA
+            | OpCode::JB => {
+                if self.condition_holds(instruction.opcode) {
+                    self.jump(instruction.immediate, &instruction)?;
                 }
             }
             OpCode::LEA => {
-                let addr = if instruction.rs1 != 0 {
-                    self.registers[instruction.rs1 as usize] + instruction.immediate
-                } else {
-                    instruction.immediate
-                };
-                self.registers[instruction.rd as usize] = addr;
+                let addr = self.effective_address(&instruction);
+                self.registers[instruction.rd as usize] = addr as i32;
             }
             OpCode::STORE => {
-                let addr = if instruction.rs1 != 0 {
-                    self.registers[instruction.rs1 as usize] as usize
-                } else {
-                    instruction.immediate as usize
-                };
+                let index = self.word_index(&instruction)?;
+                let value = self.registers[instruction.rd as usize];
+                self.write_word(index, value);
+            }
+            OpCode::LOAD => {
+                let index = self.word_index(&instruction)?;
+                self.registers[instruction.rd as usize] = self.read_word(index).unwrap_or(0);
+            }
+            OpCode::LOADB => {
+                let addr = self.effective_address(&instruction);
 
-                if addr >= self.memory.len() {
+                let byte = if addr < 0 { None } else { self.read_byte(addr as usize) };
+                let Some(byte) = byte else {
                     return Err(self.runtime_error(
-                        format!("Memory access out of bounds: address {} (max: {})",
-                                addr, self.memory.len() - 1),
+                        RuntimeErrorKind::MemoryOutOfBounds,
+                        format!("Memory access out of bounds: byte address {} (max: {})",
+                                addr, self.memory_size() - 1),
                         instruction,
                     ));
-                }
+                };
 
-                self.memory[addr] = self.registers[instruction.rd as usize];
+                self.registers[instruction.rd as usize] = byte as i32;
             }
             OpCode::PUSH => {
-                self.stack.push(self.registers[instruction.rd as usize]);
+                self.push(self.registers[instruction.rd as usize], &instruction)?;
             }
             OpCode::POP => {
-                if let Some(value) = self.stack.pop() {
+                if let Some(value) = self.pop(&instruction)? {
                     self.registers[instruction.rd as usize] = value;
                 } else {
                     return Err(self.runtime_error(
+                        RuntimeErrorKind::StackUnderflow,
                         "Stack underflow: attempted to pop from empty stack".to_string(),
                         instruction,
                     ));
                 }
             }
             OpCode::CALL => {
-                self.output_buffer.flush().unwrap();
+                self.flush_output(&instruction)?;
+                // The return address goes on the stack as an instruction index.
+                self.push((self.pc / 2) as i32, &instruction)?;
                 self.call_stack.push(self.pc);
-                self.stack.push(self.pc as i32);
-                self.pc = (instruction.immediate as usize) * 2;
+                self.jump(instruction.immediate, &instruction)?;
             }
             OpCode::RET => {
-                if let Some(addr) = self.stack.pop() {
-                    self.pc = addr as usize;
-                    self.call_stack.pop();
-                } else {
+                // The stack is ordinary memory, so the popped return address
+                // is checked against the one CALL recorded before jumping.
+                let Some(&return_pc) = self.call_stack.last() else {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::StackUnderflow,
+                        "Stack underflow: RET without a matching CALL".to_string(),
+                        instruction,
+                    ));
+                };
+                let Some(addr) = self.pop(&instruction)? else {
                     return Err(self.runtime_error(
+                        RuntimeErrorKind::StackUnderflow,
                         "Stack underflow: attempted to return with empty stack".to_string(),
                         instruction,
                     ));
+                };
+                if addr as i64 != (return_pc / 2) as i64 {
+                    return Err(self.runtime_error(
+                        RuntimeErrorKind::CorruptedReturnAddress,
+                        format!("Corrupted return address: popped {} but the matching CALL at PC {} returns to {} \
+                                 (is a PUSH or POP unbalanced?)",
+                                addr, return_pc / 2 - 1, return_pc / 2),
+                        instruction,
+                    ));
                 }
+                self.pc = return_pc;
+                self.call_stack.pop();
             }
             OpCode::HALT => {
-                self.output_buffer.flush().unwrap();
+                self.flush_output(&instruction)?;
                 self.running = false;
             }
             OpCode::NOP => {}
             OpCode::AND => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
+                let val2 = self.second_operand(&instruction);
                 let result = val1 & val2;
                 self.registers[instruction.rd as usize] = result;
                 self.set_flags(result);
             }
             OpCode::OR => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
+                let val2 = self.second_operand(&instruction);
                 let result = val1 | val2;
                 self.registers[instruction.rd as usize] = result;
                 self.set_flags(result);
             }
             OpCode::XOR => {
                 let val1 = self.registers[instruction.rs1 as usize];
-                let val2 = if instruction.rs2 != 0 {
-                    self.registers[instruction.rs2 as usize]
-                } else {
-                    instruction.immediate
-                };
+                let val2 = self.second_operand(&instruction);
                 let result = val1 ^ val2;
                 self.registers[instruction.rd as usize] = result;
                 self.set_flags(result);
@@ -400,6 +940,7 @@ impl Runtime {
 
                 if shift >= 32 {
                     return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidShift,
                         format!("Invalid left shift: shift amount {} >= 32", shift),
                         instruction,
                     ));
@@ -415,6 +956,7 @@ impl Runtime {
 
                 if shift >= 32 {
                     return Err(self.runtime_error(
+                        RuntimeErrorKind::InvalidShift,
                         format!("Invalid right shift: shift amount {} >= 32", shift),
                         instruction,
                     ));
@@ -425,47 +967,39 @@ impl Runtime {
                 self.set_flags(result);
             }
             OpCode::PRINT => {
-                self.output_buffer.flush().unwrap();
+                self.flush_output(&instruction)?;
                 let value = self.registers[instruction.rd as usize];
-                write!(self.output_buffer, "{}", value).unwrap();
+                write!(self.output, "{}", value).map_err(|e| self.output_error(e, &instruction))?;
             }
             OpCode::PRINTC => {
+                // Output is written byte for byte, so UTF-8 text printed one
+                // byte at a time (or as a whole string) comes out intact.
                 let value = self.registers[instruction.rd as usize] as u8;
                 if value == 0 {
-                    let mut addr = 512;
-                    loop {
-                        if addr >= self.memory.len() * 4 {
-                            break;
-                        }
-                        let word_addr = addr / 4;
-                        let byte_offset = addr % 4;
-                        let word = self.memory[word_addr];
-                        let byte = ((word >> (byte_offset * 8)) & 0xFF) as u8;
+                    let mut bytes = Vec::new();
+                    let mut addr = DATA_BASE;
+                    while let Some(byte) = self.read_byte(addr) {
                         if byte == 0 {
                             break;
                         }
-                        write!(self.output_buffer, "{}", byte as char).unwrap();
+                        bytes.push(byte);
                         addr += 1;
                     }
+                    self.output.write_all(&bytes).map_err(|e| self.output_error(e, &instruction))?;
                 } else {
-                    write!(self.output_buffer, "{}", value as char).unwrap();
+                    self.output.write_all(&[value]).map_err(|e| self.output_error(e, &instruction))?;
                 }
             }
             OpCode::INPUT => {
-                self.output_buffer.flush().unwrap();
+                self.flush_output(&instruction)?;
                 let mut input = String::new();
-                io::stdout().flush().unwrap();
 
-                if io::stdin().read_line(&mut input).is_ok() {
+                if self.input.read_line(&mut input).is_ok() {
                     let trimmed = input.trim();
 
-                    let input_mode = if instruction.rs1 != 0 {
-                        self.registers[instruction.rs1 as usize]
-                    } else {
-                        return Err(self.runtime_error(
-                            "Input mode register required for INPUT instruction".to_string(),
-                            instruction,
-                        ));
+                    let input_mode = match instruction.mode {
+                        OperandMode::Register => self.registers[instruction.rs1 as usize],
+                        OperandMode::Immediate => instruction.immediate,
                     };
 
                     match input_mode {
@@ -478,6 +1012,7 @@ impl Runtime {
                                     return match trimmed.parse::<i64>() {
                                         Ok(big_value) => {
                                             Err(self.runtime_error(
+                                                RuntimeErrorKind::InvalidInput,
                                                 format!("Input integer overflow: {} is outside the range of 32-bit signed integers ({} to {})",
                                                         big_value, i32::MIN, i32::MAX),
                                                 instruction,
@@ -485,6 +1020,7 @@ impl Runtime {
                                         }
                                         Err(_) => {
                                             Err(self.runtime_error(
+                                                RuntimeErrorKind::InvalidInput,
                                                 format!("Invalid integer input: '{}' is not a valid integer", trimmed),
                                                 instruction,
                                             ))
@@ -501,42 +1037,24 @@ impl Runtime {
                             }
                         }
                         2 => {
-                            let base_addr = if instruction.rs2 != 0 {
-                                self.registers[instruction.rs2 as usize] as usize
-                            } else {
+                            // The string and its terminating NUL must fit
+                            // entirely; nothing is written otherwise.
+                            let base_addr = self.registers[instruction.rs2 as usize] as i64;
+                            let end = base_addr + trimmed.len() as i64 + 1;
+                            if base_addr < 0 || end > self.memory_size() as i64 {
                                 return Err(self.runtime_error(
-                                    "String input mode requires base address in rs2 register".to_string(),
+                                    RuntimeErrorKind::MemoryOutOfBounds,
+                                    format!("String input of {} bytes at address {} exceeds memory bounds (size: {})",
+                                            trimmed.len(), base_addr, self.memory_size()),
                                     instruction,
                                 ));
-                            };
-
-                            self.registers[instruction.rd as usize] = trimmed.len() as i32;
-
-                            for (i, byte) in trimmed.bytes().enumerate() {
-                                let addr = base_addr + i;
-                                if addr >= self.memory.len() * 4 {
-                                    return Err(self.runtime_error(
-                                        format!("String input exceeds memory bounds at address {}", addr),
-                                        instruction,
-                                    ));
-                                }
-
-                                let word_addr = addr / 4;
-                                let byte_offset = addr % 4;
-                                let current = self.memory[word_addr];
-                                let mask = !(0xFF << (byte_offset * 8));
-                                let new_val = (current & mask) | ((byte as i32) << (byte_offset * 8));
-                                self.memory[word_addr] = new_val;
                             }
 
-                            let null_addr = base_addr + trimmed.len();
-                            if null_addr < self.memory.len() * 4 {
-                                let word_addr = null_addr / 4;
-                                let byte_offset = null_addr % 4;
-                                let current = self.memory[word_addr];
-                                let mask = !(0xFF << (byte_offset * 8));
-                                self.memory[word_addr] = current & mask;
+                            let base_addr = base_addr as usize;
+                            for (i, byte) in trimmed.bytes().chain([0]).enumerate() {
+                                self.write_byte(base_addr + i, byte);
                             }
+                            self.registers[instruction.rd as usize] = trimmed.len() as i32;
                         }
                         3 => {
                             match trimmed.parse::<f32>() {
@@ -545,6 +1063,7 @@ impl Runtime {
                                 }
                                 Err(_) => {
                                     return Err(self.runtime_error(
+                                        RuntimeErrorKind::InvalidInput,
                                         format!("Invalid float input: '{}' is not a valid floating point number", trimmed),
                                         instruction,
                                     ));
@@ -553,6 +1072,7 @@ impl Runtime {
                         }
                         _ => {
                             return Err(self.runtime_error(
+                                RuntimeErrorKind::InvalidInputMode,
                                 format!("Invalid input mode: {} (valid modes: 0=integer, 1=character, 2=string, 3=float)", input_mode),
                                 instruction,
                             ));
@@ -560,7 +1080,8 @@ impl Runtime {
                     }
                 } else {
                     return Err(self.runtime_error(
-                        "Failed to read input from stdin".to_string(),
+                        RuntimeErrorKind::Io,
+                        "Failed to read input".to_string(),
                         instruction,
                     ));
                 }
@@ -569,34 +1090,111 @@ impl Runtime {
         Ok(())
     }
 
-    pub(crate) fn run(&mut self) -> Result<(), RuntimeError> {
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.run_with_limits(ExecutionLimits::new())
+    }
+
+    /// Runs until HALT, like `run`, but stops with an `ExecutionLimitExceeded`
+    /// error once the program exceeds `limits`. The error points at the next
+    /// instruction, which has not been executed.
+    pub fn run_with_limits(&mut self, limits: ExecutionLimits) -> Result<(), RuntimeError> {
+        self.run_loop(limits, false)
+    }
+
+    fn run_loop(&mut self, limits: ExecutionLimits, trace: bool) -> Result<(), RuntimeError> {
+        let started = Instant::now();
+        let mut steps = 0;
         while self.running {
-            if let Some(instruction) = self.fetch() {
-                if let Err(error) = self.execute(instruction) {
+            let instruction = match self.next_instruction() {
+                Ok(Some(instruction)) => instruction,
+                Ok(None) => break,
+                Err(error) => {
+                    let _ = self.output.flush();
                     return Err(error);
                 }
-            } else {
-                break;
+            };
+
+            if let Some(max_steps) = limits.max_steps
+                && steps >= max_steps
+            {
+                let message = format!(
+                    "Execution limit exceeded: {} instructions executed without halting (limit: {})",
+                    steps, max_steps
+                );
+                return Err(self.limit_error(message, instruction));
+            }
+            if let Some(timeout) = limits.timeout
+                && steps % TIMEOUT_CHECK_INTERVAL == 0
+                && started.elapsed() >= timeout
+            {
+                let message = format!(
+                    "Execution limit exceeded: still running after {:?} ({} instructions executed)",
+                    timeout, steps
+                );
+                return Err(self.limit_error(message, instruction));
+            }
+
+            self.advance();
+            if trace {
+                self.debug_instruction(&instruction);
             }
+            if let Err(error) = self.execute(instruction) {
+                // Keep whatever the program printed before it failed.
+                let _ = self.output.flush();
+                return Err(error);
+            }
+            steps += 1;
         }
         Ok(())
     }
 
-    pub(crate) fn run_with_trace(&mut self) -> Result<(), RuntimeError> {
-        while self.running {
-            if let Some(instruction) = self.fetch() {
-                self.debug_instruction();
-                if let Err(error) = self.execute(instruction) {
-                    return Err(error);
-                }
-            } else {
-                break;
+    /// An `ExecutionLimitExceeded` error for the instruction at the PC, which
+    /// is about to run.
+    fn limit_error(&mut self, message: String, instruction: Instruction) -> RuntimeError {
+        let _ = self.output.flush();
+        RuntimeError::new(
+            RuntimeErrorKind::ExecutionLimitExceeded,
+            message,
+            self.pc / 2,
+            Some(instruction),
+            self.create_stack_trace(self.pc),
+            self.instruction_count,
+        )
+    }
+
+    /// Executes a single instruction. Returns `Ok(false)` once the program has
+    /// halted or run off the end of the code.
+    pub fn step(&mut self) -> Result<bool, RuntimeError> {
+        if !self.running {
+            return Ok(false);
+        }
+        match self.fetch()? {
+            Some(instruction) => {
+                self.execute(instruction)?;
+                Ok(self.is_running())
             }
+            None => Ok(false),
         }
-        Ok(())
     }
 
-    pub(crate) fn debug_state(&self) {
+    /// Executes at most `budget` instructions. Returns `Ok(true)` if the
+    /// program is still running when the budget is used up, so the caller can
+    /// resume it with another call.
+    pub fn run_for(&mut self, budget: usize) -> Result<bool, RuntimeError> {
+        for _ in 0..budget {
+            if !self.step()? {
+                return Ok(false);
+            }
+        }
+        Ok(self.is_running())
+    }
+
+    /// Runs like `run_with_limits`, printing each instruction as it executes.
+    pub fn run_with_trace(&mut self, limits: ExecutionLimits) -> Result<(), RuntimeError> {
+        self.run_loop(limits, true)
+    }
+
+    pub fn debug_state(&self) {
         println!("VM Debug State");
         println!("PC: {} (instruction #{})", self.pc / 2, self.instruction_count);
 
@@ -613,47 +1211,53 @@ impl Runtime {
 
         println!("Flags: Z={} N={} C={} V={}",
                  self.flags.zero, self.flags.negative, self.flags.carry, self.flags.overflow);
-        println!("Stack: {} items", self.stack.len());
+        println!("Stack: {} items (SP: {})", self.stack_depth(), self.registers[STACK_POINTER]);
         println!("Call stack: {} deep", self.call_stack.len());
         println!();
     }
 
-    pub(crate) fn debug_instruction(&self) {
-        if let Some(instruction) = self.get_instruction_at_pc(self.pc - 2) {
-            println!("🔍 Executing: {:?} rd={}, rs1={}, rs2={}, imm={}",
-                     instruction.opcode, instruction.rd, instruction.rs1,
-                     instruction.rs2, instruction.immediate);
-        }
+    /// Prints an instruction that has been fetched. It is passed in rather than
+    /// read back from the PC, which a jump may already have moved.
+    pub fn debug_instruction(&self, instruction: &Instruction) {
+        println!("🔍 Executing: {:?} rd={}, rs1={}, rs2={}, imm={}",
+                 instruction.opcode, instruction.rd, instruction.rs1,
+                 instruction.rs2, instruction.immediate);
     }
 
-    pub(crate) fn debug_memory(&self, start_addr: usize, count: usize) {
+    /// Prints `count` words of data memory starting at byte address
+    /// `start_addr` (rounded down to a word boundary).
+    pub fn debug_memory(&self, start_addr: usize, count: usize) {
         for i in 0..count {
-            let addr = start_addr + i;
-            if addr < self.memory.len() {
-                let value = self.memory[addr];
+            let Some(addr) = (start_addr / 4 * 4).checked_add(i * 4) else {
+                break;
+            };
+            if let Some(value) = self.read_word(addr / 4) {
                 println!("  [{:3}]: {:10} (0x{:08x})", addr, value, value as u32);
             }
         }
     }
 
-    pub(crate) fn debug_stack(&self) {
-        println!("Stack contents ({} items):", self.stack.len());
-        if self.stack.is_empty() {
+    pub fn debug_stack(&self) {
+        println!("Stack contents ({} items, top first):", self.stack_depth());
+        if self.stack_depth() == 0 {
             println!("  (empty)");
         } else {
-            for (i, &value) in self.stack.iter().rev().enumerate() {
-                println!("  [{}]: {} (0x{:08x})",
-                         self.stack.len() - 1 - i, value, value as u32);
+            let sp = self.registers[STACK_POINTER] as usize;
+            for addr in (sp..self.memory_size()).step_by(4) {
+                if let Some(value) = self.read_word(addr / 4) {
+                    println!("  [{}]: {} (0x{:08x})", addr, value, value as u32);
+                }
             }
         }
     }
 
-    pub(crate) fn debug_call_stack(&self) {
+    pub fn debug_call_stack(&self) {
         println!("Call stack ({} deep):", self.call_stack.len());
         if self.call_stack.is_empty() {
             println!("  (empty)");
         } else {
-            for (i, &pc) in self.call_stack.iter().rev().enumerate() {
+            for (i, &return_pc) in self.call_stack.iter().rev().enumerate() {
+                let pc = return_pc - 2;
                 if let Some(instruction) = self.get_instruction_at_pc(pc) {
                     println!("  [{}]: PC {} -> {:?}", i, pc / 2, instruction.opcode);
                 } else {
@@ -663,21 +1267,17 @@ impl Runtime {
         }
     }
 
-    pub(crate) fn debug_data_section(&self) {
+    pub fn debug_data_section(&self) {
         println!("Data section (strings):");
-        let mut addr = 512;
+        let mut addr = DATA_BASE;
         let mut string_count = 0;
 
-        while addr < self.memory.len() * 4 && string_count < 10 {
+        while addr < self.memory_size() && string_count < 10 {
             let start_addr = addr;
             let mut string_bytes = Vec::new();
             let mut found_string = false;
 
-            while addr < self.memory.len() * 4 {
-                let word_addr = addr / 4;
-                let byte_offset = addr % 4;
-                let word = self.memory[word_addr];
-                let byte = ((word >> (byte_offset * 8)) & 0xFF) as u8;
+            while let Some(byte) = self.read_byte(addr) {
 
                 if byte == 0 {
                     if found_string {
@@ -719,19 +1319,19 @@ impl Runtime {
         }
     }
 
-    pub(crate) fn debug_performance(&self) {
+    pub fn debug_performance(&self) {
         println!("⚡ Performance stats:");
         println!("  Instructions executed: {}", self.instruction_count);
-        println!("  Memory usage: {}/{} words",
-                 self.memory.iter().filter(|&&x| x != 0).count(),
-                 self.memory.len());
-        println!("  Stack peak: {} items", self.stack.len());
+        println!("  Code size: {} instructions", self.code.len() / 2);
+        println!("  Memory usage: {}/{} words ({} allocated)",
+                 self.memory.iter().chain(&self.stack).filter(|&&x| x != 0).count(),
+                 self.memory_limit,
+                 self.memory.len() + self.stack.len());
+        println!("  Stack depth: {} items", self.stack_depth());
         println!("  Call depth peak: {}", self.call_stack.len());
     }
 
-    pub(crate) fn debug_step(&mut self) -> Result<bool, RuntimeError> {
-        use std::io::{stdin, Write};
-
+    pub fn debug_step(&mut self) -> Result<bool, RuntimeError> {
         loop {
             println!("\nDebugger (PC: {}, instruction #{})", self.pc / 2, self.instruction_count);
 
@@ -745,14 +1345,12 @@ impl Runtime {
             io::stdout().flush().unwrap();
 
             let mut input = String::new();
-            if stdin().read_line(&mut input).is_ok() {
+            if self.input.read_line(&mut input).is_ok() {
                 match input.trim().to_lowercase().as_str() {
                     "s" | "step" => {
-                        return if let Some(instruction) = self.fetch() {
-                            if let Err(error) = self.execute(instruction.clone()) {
-                                return Err(error);
-                            }
-                            self.debug_instruction();
+                        return if let Some(instruction) = self.fetch()? {
+                            self.execute(instruction.clone())?;
+                            self.debug_instruction(&instruction);
                             Ok(self.running)
                         } else {
                             println!("Program ended");
@@ -769,10 +1367,10 @@ impl Runtime {
                         print!("Enter start address: ");
                         io::stdout().flush().unwrap();
                         let mut addr_input = String::new();
-                        if stdin().read_line(&mut addr_input).is_ok() {
-                            if let Ok(addr) = addr_input.trim().parse::<usize>() {
-                                self.debug_memory(addr, 8);
-                            }
+                        if self.input.read_line(&mut addr_input).is_ok()
+                            && let Ok(addr) = addr_input.trim().parse::<usize>()
+                        {
+                            self.debug_memory(addr, 8);
                         }
                     }
                     "st" | "stack" => {
@@ -795,4 +1393,172 @@ impl Runtime {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::*;
+    use crate::fam::FamImage;
+
+    fn instruction(opcode: OpCode, rd: u8, rs1: u8, rs2: u8, immediate: i32) -> Instruction {
+        let mode = if rs1 == 0 && rs2 == 0 { OperandMode::Immediate } else { OperandMode::Register };
+        Instruction { opcode, rd, rs1, rs2, immediate, mode }
+    }
+
+    fn bytecode(text: Vec<u8>, data: Vec<u8>, entry: u32) -> Vec<u8> {
+        FamImage { entry, data, text }.to_bytes()
+    }
+
+    fn program(instructions: &[Instruction]) -> Vec<u8> {
+        bytecode(instructions.iter().flat_map(Instruction::encode).collect(), Vec::new(), 0)
+    }
+
+    fn runtime() -> Runtime<io::Empty, Vec<u8>> {
+        Runtime::with_io(io::empty(), Vec::new())
+    }
+
+    fn load_error(bytecode: &[u8]) -> LoadErrorKind {
+        runtime().load_program(bytecode).expect_err("program should be rejected").kind
+    }
+
+    #[test]
+    fn verify_rejects_registers_that_do_not_exist() {
+        let bytes = program(&[
+            instruction(OpCode::NOP, 0, 0, 0, 0),
+            instruction(OpCode::ADD, 1, 2, 32, 0),
+        ]);
+        assert_eq!(load_error(&bytes), LoadErrorKind::InvalidRegister { instruction: 1, register: 32 });
+
+        let bytes = program(&[instruction(OpCode::MOV, 200, 0, 0, 1)]);
+        assert_eq!(load_error(&bytes), LoadErrorKind::InvalidRegister { instruction: 0, register: 200 });
+    }
+
+    #[test]
+    fn verify_checks_jump_targets() {
+        let bytes = program(&[instruction(OpCode::NOP, 0, 0, 0, 0), instruction(OpCode::JEQ, 0, 0, 0, 3)]);
+        assert_eq!(load_error(&bytes), LoadErrorKind::InvalidJumpTarget { instruction: 1, target: 3 });
+
+        let bytes = program(&[instruction(OpCode::CALL, 0, 0, 0, -1)]);
+        assert_eq!(load_error(&bytes), LoadErrorKind::InvalidJumpTarget { instruction: 0, target: -1 });
+
+        // Jumping to the end of the code is allowed: it ends the program.
+        let bytes = program(&[instruction(OpCode::JMP, 0, 0, 0, 1)]);
+        let mut vm = runtime();
+        vm.load_program(&bytes).unwrap();
+        assert!(!vm.run_for(10).unwrap());
+    }
+
+    #[test]
+    fn load_rejects_bad_layouts() {
+        assert_eq!(load_error(&bytecode(vec![0; 12], Vec::new(), 0)), LoadErrorKind::Truncated);
+        assert_eq!(load_error(&bytecode(vec![0; 16], Vec::new(), 2)), LoadErrorKind::InvalidStart);
+
+        let data = vec![0; DEFAULT_MEMORY_SIZE];
+        assert_eq!(load_error(&bytecode(Vec::new(), data, 0)), LoadErrorKind::DataTooLarge);
+
+        let mut bytes = program(&[instruction(OpCode::HALT, 0, 0, 0, 0)]);
+        bytes[0] = 0;
+        assert_eq!(load_error(&bytes), LoadErrorKind::BadMagic);
+    }
+
+    #[test]
+    fn failed_load_leaves_the_runtime_unchanged() {
+        let mut vm = runtime();
+        vm.load_program(&program(&[instruction(OpCode::MOV, 1, 0, 0, 7)])).unwrap();
+        vm.run().unwrap();
+        assert!(vm.load_program(&program(&[instruction(OpCode::ADD, 40, 0, 0, 0)])).is_err());
+        assert_eq!(vm.register(1), Some(7));
+        assert_eq!(vm.instruction_count(), 1);
+    }
+
+    #[test]
+    fn load_resets_the_previous_program_state() {
+        let mut vm = runtime().with_memory_size(1024);
+        let first = program(&[
+            instruction(OpCode::MOV, 1, 0, 0, 1),
+            instruction(OpCode::PUSH, 1, 0, 0, 0),
+            instruction(OpCode::PRINT, 1, 0, 0, 0),
+            instruction(OpCode::HALT, 0, 0, 0, 0),
+        ]);
+        vm.load_program(&first).unwrap();
+        vm.run().unwrap();
+
+        let second = program(&[instruction(OpCode::PRINT, 1, 0, 0, 0), instruction(OpCode::HALT, 0, 0, 0, 0)]);
+        vm.load_program(&second).unwrap();
+        assert_eq!(vm.register(STACK_POINTER), Some(1024));
+        assert_eq!(vm.instruction_count(), 0);
+        vm.run().unwrap();
+        assert_eq!(vm.read_memory(1020, 4), Some(vec![0; 4]));
+        assert_eq!(vm.into_output(), b"10");
+    }
+
+    #[test]
+    fn illegal_instruction_is_reported_when_reached() {
+        let mut text = instruction(OpCode::NOP, 0, 0, 0, 0).encode().to_vec();
+        text.extend_from_slice(&[0x7F, 0, 0, 0, 0, 0, 0, 0]);
+        let mut vm = runtime();
+        vm.load_program(&bytecode(text, Vec::new(), 0)).unwrap();
+
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::IllegalInstruction { pc: 1, raw_word: 0x7F00_0000 });
+        assert_eq!(error.pc, 1);
+        assert!(error.instruction.is_none());
+    }
+
+    #[test]
+    fn running_off_the_end_is_an_error_when_halt_is_required() {
+        let bytes = program(&[instruction(OpCode::NOP, 0, 0, 0, 0)]);
+        let mut vm = runtime().with_halt_required(true);
+        vm.load_program(&bytes).unwrap();
+        assert_eq!(vm.run().unwrap_err().kind, RuntimeErrorKind::PcOutOfBounds { pc: 1 });
+
+        let mut vm = runtime();
+        vm.load_program(&bytes).unwrap();
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn step_limit_stops_a_program_that_never_halts() {
+        let bytes = program(&[instruction(OpCode::NOP, 0, 0, 0, 0), instruction(OpCode::JMP, 0, 0, 0, 0)]);
+        let mut vm = runtime();
+        vm.load_program(&bytes).unwrap();
+
+        let error = vm.run_with_limits(ExecutionLimits::new().with_max_steps(5)).unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::ExecutionLimitExceeded);
+        assert_eq!(error.instruction_count, 5);
+        assert_eq!(error.pc, 1);
+    }
+
+    #[test]
+    fn error_pc_is_the_failing_instruction_index() {
+        let bytes = program(&[
+            instruction(OpCode::CALL, 0, 0, 0, 2),
+            instruction(OpCode::HALT, 0, 0, 0, 0),
+            instruction(OpCode::MOV, 1, 0, 0, 0),
+            instruction(OpCode::DIV, 1, 1, 1, 0),
+        ]);
+        let mut vm = runtime();
+        vm.load_program(&bytes).unwrap();
+
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::DivisionByZero);
+        assert_eq!(error.pc, 3);
+        let frames: Vec<usize> = error.stack_trace.iter().map(|frame| frame.pc).collect();
+        assert_eq!(frames, [3, 0]);
+    }
+
+    #[test]
+    fn stack_words_do_not_allocate_the_memory_below_them() {
+        let mut vm = runtime().with_memory_size(1 << 30);
+        let bytes = program(&[
+            instruction(OpCode::MOV, 1, 0, 0, 9),
+            instruction(OpCode::PUSH, 1, 0, 0, 0),
+            instruction(OpCode::POP, 2, 0, 0, 0),
+            instruction(OpCode::HALT, 0, 0, 0, 0),
+        ]);
+        vm.load_program(&bytes).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.register(2), Some(9));
+        assert!(vm.memory.len() + vm.stack.len() < 1024);
+    }
+}
@@ -1,38 +1,92 @@
+use std::error::Error;
+use std::fmt;
 use crate::runtime::{Instruction, StackFrame};
 
+/// The category of a runtime error, for callers that need to react to
+/// specific failures. `RuntimeError::message` carries the details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    DivisionByZero,
+    MemoryOutOfBounds,
+    MisalignedAccess,
+    StackUnderflow,
+    StackOverflow,
+    CorruptedReturnAddress,
+    ExecutionLimitExceeded,
+    InvalidShift,
+    InvalidInput,
+    InvalidInputMode,
+    Io,
+    /// The word at instruction index `pc` is not a valid instruction.
+    IllegalInstruction { pc: usize, raw_word: u32 },
+    /// Execution reached instruction index `pc`, which is outside the code.
+    PcOutOfBounds { pc: usize },
+}
+
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
     pub message: String,
+    /// Index of the instruction that failed, as `Runtime::pc()` counts them.
     pub pc: usize,
-    pub instruction: Instruction,
+    /// The instruction being executed, or `None` if there was no valid one.
+    pub instruction: Option<Instruction>,
     pub stack_trace: Vec<StackFrame>,
+    /// The runtime's `instruction_count()` when the error was raised.
+    pub instruction_count: usize,
 }
 
 impl RuntimeError {
-    pub fn new(message: String, pc: usize, instruction: Instruction, stack_trace: Vec<StackFrame>) -> Self {
+    pub fn new(
+        kind: RuntimeErrorKind,
+        message: String,
+        pc: usize,
+        instruction: Option<Instruction>,
+        stack_trace: Vec<StackFrame>,
+        instruction_count: usize,
+    ) -> Self {
         RuntimeError {
+            kind,
             message,
             pc,
             instruction,
             stack_trace,
+            instruction_count,
         }
     }
 
     pub fn print_error(&self) {
         eprintln!("Runtime Error: {}", self.message);
-        eprintln!("  at PC: {} (instruction: {:?})", self.pc / 2, self.instruction.opcode);
+        match &self.instruction {
+            Some(instruction) => eprintln!("  at PC: {} (instruction: {:?})", self.pc, instruction.opcode),
+            None => eprintln!("  at PC: {}", self.pc),
+        }
+        eprintln!("  after {} instructions", self.instruction_count);
 
         if !self.stack_trace.is_empty() {
             eprintln!("\nStack trace:");
             for (i, frame) in self.stack_trace.iter().rev().enumerate() {
-                eprintln!("  #{}: PC {} - {}", i, frame.pc / 2, frame.instruction);
+                eprintln!("  #{}: PC {} - {}", i, frame.pc, frame.instruction);
             }
         }
 
-        eprintln!("\nInstruction details:");
-        eprintln!("  Opcode: {:?}", self.instruction.opcode);
-        eprintln!("  Registers: rd={}, rs1={}, rs2={}",
-                  self.instruction.rd, self.instruction.rs1, self.instruction.rs2);
-        eprintln!("  Immediate: {}", self.instruction.immediate);
+        if let Some(instruction) = &self.instruction {
+            eprintln!("\nInstruction details:");
+            eprintln!("  Opcode: {:?}", instruction.opcode);
+            eprintln!("  Registers: rd={}, rs1={}, rs2={}",
+                      instruction.rd, instruction.rs1, instruction.rs2);
+            eprintln!("  Immediate: {}", instruction.immediate);
+        }
     }
 }
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.instruction {
+            Some(instruction) => write!(f, "{} at PC {} ({:?})", self.message, self.pc, instruction.opcode),
+            None => write!(f, "{} at PC {}", self.message, self.pc),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use folia_am::{disassemble, Assembler, ExecutionLimits, Runtime, SourceFile};
+
+/// Instructions a test program may execute before it is considered hung.
+const STEP_BUDGET: usize = 10_000_000;
+
+/// A program with a golden `.out` and/or `.err` file, plus any sources it
+/// links against.
+struct GoldenTest {
+    program: PathBuf,
+    sources: Vec<PathBuf>,
+    expected: Option<PathBuf>,
+    expected_error: Option<PathBuf>,
+    input: Option<PathBuf>,
+}
+
+#[inline]
+fn is_source(path: &Path) -> bool {
+    matches!(path.extension().and_then(|s| s.to_str()), Some("s") | Some("asm"))
+}
+
+/// Finds the test for `program`, if it has a companion `.out` or `.err` file.
+///
+/// `x.s` is run with `x.in` as stdin (when present) and its output compared
+/// against `x.out` (empty if there is none). With an `x.err`, the program must
+/// stop with that error message instead of running to completion. A `main.s`
+/// is linked together with the other sources in its directory, so multi-file
+/// programs can be tested as well.
+fn golden_test(program: &Path) -> Result<Option<GoldenTest>, String> {
+    let expected = Some(program.with_extension("out")).filter(|path| path.is_file());
+    let expected_error = Some(program.with_extension("err")).filter(|path| path.is_file());
+    if expected.is_none() && expected_error.is_none() {
+        return Ok(None);
+    }
+
+    let input = Some(program.with_extension("in")).filter(|path| path.is_file());
+    let mut sources = vec![program.to_path_buf()];
+    if program.file_stem().and_then(|s| s.to_str()) == Some("main") {
+        let dir = program.parent().unwrap_or(Path::new("."));
+        let mut siblings = source_files_in(dir)?;
+        siblings.retain(|path| path != program);
+        sources.extend(siblings);
+    }
+
+    Ok(Some(GoldenTest {
+        program: program.to_path_buf(),
+        sources,
+        expected,
+        expected_error,
+        input,
+    }))
+}
+
+fn source_files_in(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Error reading directory {}: {}", dir.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_source(path))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Collects the tests under `path`: the file itself, or every source file
+/// with a `.out` companion anywhere below a directory.
+fn collect_tests(path: &Path, tests: &mut Vec<GoldenTest>) -> Result<(), String> {
+    if path.is_file() {
+        return match golden_test(path)? {
+            Some(test) => {
+                tests.push(test);
+                Ok(())
+            }
+            None => Err(format!(
+                "No expected output for {} (looked for {} or {})",
+                path.display(),
+                path.with_extension("out").display(),
+                path.with_extension("err").display()
+            )),
+        };
+    }
+
+    let entries = fs::read_dir(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    let mut children: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    children.sort();
+
+    for child in children {
+        if child.is_dir() {
+            collect_tests(&child, tests)?;
+        } else if is_source(&child)
+            && let Some(test) = golden_test(&child)?
+        {
+            tests.push(test);
+        }
+    }
+    Ok(())
+}
+
+fn run_test(test: &GoldenTest) -> Result<(), String> {
+    let sources = test
+        .sources
+        .iter()
+        .map(|path| {
+            fs::read_to_string(path)
+                .map(|text| SourceFile::new(path.display().to_string(), text))
+                .map_err(|e| format!("Error reading file {}: {}", path.display(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let read = |path: Option<&Path>| match path {
+        Some(path) => fs::read(path).map_err(|e| format!("Error reading {}: {}", path.display(), e)),
+        None => Ok(Vec::new()),
+    };
+    let expected = read(test.expected.as_deref())?;
+    let expected_error = match test.expected_error.as_deref() {
+        Some(path) => Some(String::from_utf8_lossy(&read(Some(path))?).trim_end().to_string()),
+        None => None,
+    };
+    let input = read(test.input.as_deref())?;
+
+    let bytecode = Assembler::new().assemble(&sources).map_err(|errors| {
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        messages.join("\n\n")
+    })?;
+    check_round_trip(&bytecode)?;
+
+    let mut vm = Runtime::with_io(input.as_slice(), Vec::new());
+    vm.load_program(&bytecode).map_err(|e| format!("Load error: {}", e))?;
+    let error = vm
+        .run_with_limits(ExecutionLimits::new().with_max_steps(STEP_BUDGET))
+        .err()
+        .map(|e| format!("Runtime Error: {}", e));
+    match (expected_error, error) {
+        (None, Some(error)) => return Err(error),
+        (Some(expected), None) => {
+            return Err(format!("Expected the program to fail with:\n  {}\nbut it finished", expected));
+        }
+        (Some(expected), Some(error)) if expected != error => {
+            return Err(format!("Error differs (- expected, + actual):\n    - {}\n    + {}", expected, error));
+        }
+        _ => {}
+    }
+
+    let actual = vm.into_output();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(diff(&String::from_utf8_lossy(&expected), &String::from_utf8_lossy(&actual)))
+    }
+}
+
+/// Checks that the program's disassembly assembles back to the same bytes.
+fn check_round_trip(bytecode: &[u8]) -> Result<(), String> {
+    let source = disassemble(bytecode).map_err(|e| format!("Disassembly error: {}", e))?;
+    let reassembled = Assembler::new().assemble_str("<disassembly>", &source).map_err(|errors| {
+        let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        format!("Disassembly does not reassemble:\n{}", messages.join("\n\n"))
+    })?;
+    if reassembled != bytecode {
+        return Err("Disassembly reassembles to different bytecode".to_string());
+    }
+    Ok(())
+}
+
+/// Line-by-line comparison listing each line that differs.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.split('\n').collect();
+    let actual: Vec<&str> = actual.split('\n').collect();
+    let mut out = String::from("Output differs (- expected, + actual):");
+
+    for i in 0..expected.len().max(actual.len()) {
+        let (want, got) = (expected.get(i), actual.get(i));
+        if want == got {
+            continue;
+        }
+        out.push_str(&format!("\n  line {}:", i + 1));
+        match want {
+            Some(line) => out.push_str(&format!("\n    - {:?}", line)),
+            None => out.push_str("\n    - (missing)"),
+        }
+        match got {
+            Some(line) => out.push_str(&format!("\n    + {:?}", line)),
+            None => out.push_str("\n    + (missing)"),
+        }
+    }
+    out
+}
+
+/// Runs every golden test found under `paths` and prints a pass/fail line for
+/// each. Returns whether all of them passed.
+pub(crate) fn run_tests(paths: &[String]) -> Result<bool, String> {
+    let mut tests = Vec::new();
+    for path in paths {
+        collect_tests(Path::new(path), &mut tests)?;
+    }
+    if tests.is_empty() {
+        return Err("No tests found (a test is a .s/.asm file with a matching .out or .err file)".to_string());
+    }
+
+    let mut failed = 0;
+    for test in &tests {
+        match run_test(test) {
+            Ok(()) => println!("PASS {}", test.program.display()),
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL {}", test.program.display());
+                for line in reason.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", tests.len() - failed, failed);
+    Ok(failed == 0)
+}